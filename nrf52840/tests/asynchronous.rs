@@ -22,15 +22,23 @@ mod tests {
     use embassy_time::Delay;
     use embedded_hal_bus::spi::ExclusiveDevice;
     use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
-    use mx25r::{asynchronous::AsyncMX25R6435F, SECTOR_SIZE};
+    use mx25r::{
+        asynchronous::AsyncMX25R6435F,
+        erase::ErasePlan,
+        error::Error,
+        poll::Backoff,
+        power::PowerThresholds,
+        register::{BurstLength, PowerMode},
+        BLOCK64_SIZE, SECTOR_SIZE, SECURE_OTP_SIZE,
+    };
 
     bind_interrupts!(struct Irqs {
         SPIM3 => spim::InterruptHandler<peripherals::SPI3>;
     });
 
     #[init]
-    async fn init() -> AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>
-    {
+    async fn init(
+    ) -> AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay> {
         let cfg = Config::default();
         let p = embassy_nrf::init(cfg);
 
@@ -38,43 +46,79 @@ mod tests {
         let spi = Spim::new(p.SPI3, Irqs, p.P0_19, p.P0_21, p.P0_20, spi_config);
         let cs = Output::new(p.P0_17, Level::High, OutputDrive::Standard);
         let spi_dev = ExclusiveDevice::new(spi, cs, Delay).unwrap();
-        AsyncMX25R6435F::new(spi_dev)
+        AsyncMX25R6435F::new(spi_dev, Delay, Backoff::default())
     }
 
     #[test]
     async fn basic(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         let mut buff = [0];
         let addr = 0;
 
         memory.read(addr, &mut buff).await.unwrap();
-        memory.erase_sector(addr).await.unwrap();
+        memory
+            .erase_sector(addr)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
 
         memory.read(addr, &mut buff).await.unwrap();
         defmt::assert_eq!(buff[0], 0xff);
 
-        memory.write_page(0, &[42]).await.unwrap();
+        memory
+            .write_page(0, &[42])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
 
         memory.read(addr, &mut buff).await.unwrap();
         defmt::assert_eq!(buff[0], 42);
 
-        memory.erase_sector(addr).await.unwrap();
+        memory
+            .erase_sector(addr)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
     }
     /// Read multiple bytes in a single call.
     #[test]
     async fn read_multiple_bytes(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         const LEN: usize = 16;
         let mut buf = [0u8; LEN];
-        memory.erase_sector(0).await.unwrap();
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
 
         let mut pattern = [0u8; LEN];
         for (i, e) in pattern.iter_mut().enumerate() {
             *e = i as u8;
         }
-        memory.write_page(0, &pattern).await.unwrap();
+        memory
+            .write_page(0, &pattern)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
         memory.read(0, &mut buf).await.unwrap();
         defmt::assert_eq!(&buf, &pattern);
     }
@@ -82,11 +126,20 @@ mod tests {
     /// Read spanning one sector end into the next (should error if next sector is not erased/written).
     #[test]
     async fn read_across_sector_boundary(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         let mut buf = [0u8; 16];
 
-        memory.erase_sector(0).await.unwrap();
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
         memory.read(0, &mut buf).await.unwrap();
         defmt::assert!(buf.iter().all(|&b| b == 0xFF));
     }
@@ -94,7 +147,10 @@ mod tests {
     /// Out-of-bounds reads should error.
     #[test]
     async fn read_out_of_bounds(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         let mut buf = [0u8; 16];
         let res = memory.read(0x4000_0000, &mut buf).await;
@@ -104,12 +160,27 @@ mod tests {
     /// Directly exercise the ReadNorFlash trait’s `read` method with an absolute offset.
     #[test]
     async fn direct_trait_read(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         let mut buf = [0u8; 4];
 
-        memory.erase_sector(0).await.unwrap();
-        memory.write_page(0, &[1, 2, 3, 4]).await.unwrap();
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory
+            .write_page(0, &[1, 2, 3, 4])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
 
         ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
         defmt::assert_eq!(buf, [1, 2, 3, 4]);
@@ -118,7 +189,10 @@ mod tests {
     /// Check that `capacity()` returns the expected total size.
     #[test]
     async fn trait_capacity(
-        memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         let cap = memory.capacity();
         defmt::assert_eq!(cap, 8 * 1024 * 1024);
@@ -127,7 +201,10 @@ mod tests {
     /// Write a blob at an arbitrary offset via the `NorFlash` & `NorFlash` traits, then read it back.
     #[test]
     async fn trait_write_read(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         // 4 bytes of test data
         const DATA: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
@@ -147,7 +224,10 @@ mod tests {
     /// then verify before/after.
     #[test]
     async fn trait_erase_range(
-        mut memory: AsyncMX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
     ) {
         const LEN: usize = SECTOR_SIZE as usize + (SECTOR_SIZE / 2) as usize;
         const ERASE: u32 = 2 * SECTOR_SIZE;
@@ -169,4 +249,216 @@ mod tests {
         ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
         defmt::assert!(buf.iter().all(|&b| b == 0xFF));
     }
+
+    /// Decode the JEDEC Basic Flash Parameter Table and check it against the expected part.
+    #[test]
+    async fn sfdp_basic_flash_parameters(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        let header = memory.read_sfdp_header().await.unwrap();
+        defmt::assert!(header.parameter_headers() >= 1);
+
+        let params = memory.read_basic_flash_parameters().await.unwrap();
+        defmt::assert_eq!(params.capacity(), 8 * 1024 * 1024);
+        defmt::assert_eq!(params.erase_4k_opcode, Some(0x20));
+        defmt::assert_eq!(params.fast_read.read_1_4_4.unwrap().opcode, 0xEB);
+        defmt::assert_eq!(params.page_size, Some(256));
+    }
+
+    /// Protect the top of the array and check the reported range, then remove the protection.
+    #[test]
+    async fn protect_top_range(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        const CAPACITY: u32 = 8 * 1024 * 1024;
+        // The smallest setting protects 1/64 of the array
+        let range = CAPACITY - CAPACITY / 64..CAPACITY;
+
+        memory.protect_range(range.clone()).await.unwrap();
+        memory.wait_wip().await.unwrap();
+        defmt::assert_eq!(memory.protected_range().await.unwrap(), range);
+
+        memory.protect_range(0..0).await.unwrap();
+        memory.wait_wip().await.unwrap();
+        defmt::assert!(memory.protected_range().await.unwrap().is_empty());
+    }
+
+    #[test]
+    async fn secure_otp_read(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        let mut array = [0; 16];
+        memory.read_fast(0, &mut array).await.unwrap();
+
+        let mut buff = [0; 16];
+        let mut otp = memory.secure_otp().await.unwrap();
+        otp.read(0, &mut buff).await.unwrap();
+        defmt::assert!(matches!(
+            otp.read(SECURE_OTP_SIZE - 8, &mut buff).await,
+            Err(Error::OutOfBounds)
+        ));
+        otp.exit().await.unwrap();
+
+        // Dropping the guard exits the OTP as well
+        let otp = memory.secure_otp().await.unwrap();
+        drop(otp);
+        memory.read_fast(0, &mut buff).await.unwrap();
+        defmt::assert_eq!(buff, array);
+    }
+
+    #[test]
+    async fn deep_power_down_wake(
+        memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        let sleeping = memory.deep_power_down().await.ok().unwrap();
+        let mut memory = sleeping.wake().await.ok().unwrap();
+
+        let (manufacturer, _, _) = memory.read_identification().await.unwrap();
+        defmt::assert_eq!(manufacturer.0, 0xC2);
+    }
+
+    #[test]
+    async fn pending_operation_refuses_conflicts(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        let erase = memory.erase_sector(0).await.unwrap();
+        defmt::assert!(matches!(
+            memory.write_page(0, &[42]).await,
+            Err(Error::Busy)
+        ));
+
+        while let Err(Error::Busy) = erase.poll(&mut memory).await {}
+        erase.poll(&mut memory).await.unwrap();
+        memory
+            .write_page(0, &[42])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    async fn read_while_erasing(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        let mut expected = [0; 16];
+        memory.read_fast(0, &mut expected).await.unwrap();
+
+        let mut buff = [0; 16];
+        let erase = memory.erase_block64(BLOCK64_SIZE).await.unwrap();
+        memory.read_while_erasing(0, &mut buff).await.unwrap();
+        defmt::assert_eq!(buff, expected);
+        defmt::assert!(matches!(
+            memory.read_while_erasing(BLOCK64_SIZE, &mut buff).await,
+            Err(Error::Busy)
+        ));
+        erase.wait(&mut memory).await.unwrap();
+    }
+
+    #[test]
+    async fn read_wrapped(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        let pattern: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory
+            .write_page(0, &pattern)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        let mut buff = [0; 8];
+        defmt::assert!(matches!(
+            memory.read_wrapped(4, &mut buff).await,
+            Err(Error::Value)
+        ));
+        memory.set_burst_length(BurstLength::Wrap8).await.unwrap();
+        memory.read_wrapped(4, &mut buff).await.unwrap();
+        defmt::assert_eq!(buff, [4, 5, 6, 7, 0, 1, 2, 3]);
+        memory
+            .set_burst_length(BurstLength::Disabled)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    async fn managed_power_mode(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        memory
+            .set_power_mode(PowerMode::UltraLowPower)
+            .await
+            .unwrap();
+        memory.set_power_management(Some(PowerThresholds {
+            read: 16,
+            write: 16,
+        }));
+
+        let pattern = [0x5A; 32];
+        let mut buff = [0; 32];
+        NorFlash::erase(&mut memory, 0, SECTOR_SIZE).await.unwrap();
+        NorFlash::write(&mut memory, 0, &pattern).await.unwrap();
+        ReadNorFlash::read(&mut memory, 0, &mut buff).await.unwrap();
+        defmt::assert_eq!(buff, pattern);
+        defmt::assert_eq!(memory.power_mode(), Some(PowerMode::UltraLowPower));
+        defmt::assert_eq!(
+            memory.read_configuration().await.unwrap().power_mode,
+            PowerMode::UltraLowPower
+        );
+    }
+
+    #[test]
+    async fn trait_erase_unaligned_blocks(
+        mut memory: AsyncMX25R6435F<
+            ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>,
+            Delay,
+        >,
+    ) {
+        const FROM: u32 = SECTOR_SIZE;
+        const TO: u32 = SECTOR_SIZE + BLOCK64_SIZE;
+
+        // 7 sectors up to the first 32K block, the 32K block and the last sector
+        let plan = ErasePlan::new(FROM, TO).unwrap();
+        defmt::assert_eq!(plan.count(), 9);
+
+        NorFlash::erase(&mut memory, FROM, TO).await.unwrap();
+        let mut buf = [0u8; 16];
+        ReadNorFlash::read(&mut memory, TO - 16, &mut buf)
+            .await
+            .unwrap();
+        defmt::assert!(buf.iter().all(|&b| b == 0xFF));
+    }
 }
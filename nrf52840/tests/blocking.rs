@@ -22,14 +22,22 @@ mod tests {
     use embassy_time::Delay;
     use embedded_hal_bus::spi::ExclusiveDevice;
     use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
-    use mx25r::{blocking::MX25R6435F, SECTOR_SIZE};
+    use mx25r::{
+        blocking::MX25R6435F,
+        erase::ErasePlan,
+        error::Error,
+        power::PowerThresholds,
+        register::{BurstLength, PowerMode},
+        BLOCK64_SIZE, SECTOR_SIZE, SECURE_OTP_SIZE,
+    };
 
     bind_interrupts!(struct Irqs {
         SPIM3 => spim::InterruptHandler<peripherals::SPI3>;
     });
 
     #[init]
-    async fn init() -> MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>> {
+    async fn init(
+    ) -> MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay> {
         let cfg = Config::default();
         let p = embassy_nrf::init(cfg);
 
@@ -37,43 +45,59 @@ mod tests {
         let spi = Spim::new(p.SPI3, Irqs, p.P0_19, p.P0_21, p.P0_20, spi_config);
         let cs = Output::new(p.P0_17, Level::High, OutputDrive::Standard);
         let spi_dev = ExclusiveDevice::new(spi, cs, Delay).unwrap();
-        MX25R6435F::new(spi_dev)
+        MX25R6435F::new(spi_dev, Delay)
     }
 
     #[test]
     async fn basic(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         let mut buff = [0];
         let addr = 0;
 
         memory.read(addr, &mut buff).unwrap();
-        memory.erase_sector(addr).unwrap();
+        memory
+            .erase_sector(addr)
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
 
         memory.read(addr, &mut buff).unwrap();
         defmt::assert_eq!(buff[0], 0xff);
 
-        memory.write_page(0, &[42]).unwrap();
+        memory
+            .write_page(0, &[42])
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
 
         memory.read(addr, &mut buff).unwrap();
         defmt::assert_eq!(buff[0], 42);
 
-        memory.erase_sector(addr).unwrap();
+        memory
+            .erase_sector(addr)
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
     }
     /// Read multiple bytes in a single call.
     #[test]
     async fn read_multiple_bytes(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         const LEN: usize = 16;
         let mut buf = [0u8; LEN];
-        memory.erase_sector(0).unwrap();
+        memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
 
         let mut pattern = [0u8; LEN];
         for (i, e) in pattern.iter_mut().enumerate() {
             *e = i as u8;
         }
-        memory.write_page(0, &pattern).unwrap();
+        memory
+            .write_page(0, &pattern)
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
         memory.read(0, &mut buf).unwrap();
         defmt::assert_eq!(&buf, &pattern);
     }
@@ -81,11 +105,11 @@ mod tests {
     /// Read spanning one sector end into the next (should error if next sector is not erased/written).
     #[test]
     async fn read_across_sector_boundary(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         let mut buf = [0u8; 16];
 
-        memory.erase_sector(0).unwrap();
+        memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
         memory.read(0, &mut buf).unwrap();
         defmt::assert!(buf.iter().all(|&b| b == 0xFF));
     }
@@ -93,7 +117,7 @@ mod tests {
     /// Out-of-bounds reads should error.
     #[test]
     async fn read_out_of_bounds(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         let mut buf = [0u8; 16];
         let res = memory.read(0x4000_0000, &mut buf);
@@ -103,12 +127,16 @@ mod tests {
     /// Directly exercise the ReadNorFlash trait’s `read` method with an absolute offset.
     #[test]
     async fn direct_trait_read(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         let mut buf = [0u8; 4];
 
-        memory.erase_sector(0).unwrap();
-        memory.write_page(0, &[1, 2, 3, 4]).unwrap();
+        memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
+        memory
+            .write_page(0, &[1, 2, 3, 4])
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
 
         ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
         defmt::assert_eq!(buf, [1, 2, 3, 4]);
@@ -117,7 +145,7 @@ mod tests {
     /// Check that `capacity()` returns the expected total size.
     #[test]
     async fn trait_capacity(
-        memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         let cap = memory.capacity();
         defmt::assert_eq!(cap, 8 * 1024 * 1024);
@@ -126,7 +154,7 @@ mod tests {
     /// Write a blob at an arbitrary offset via the `NorFlash` & `NorFlash` traits, then read it back.
     #[test]
     async fn trait_write_read(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         // 4 bytes of test data
         const DATA: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
@@ -146,7 +174,7 @@ mod tests {
     /// then verify before/after.
     #[test]
     async fn trait_erase_range(
-        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>>,
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
     ) {
         const LEN: usize = SECTOR_SIZE as usize + (SECTOR_SIZE / 2) as usize;
         const ERASE: u32 = 2 * SECTOR_SIZE;
@@ -168,4 +196,168 @@ mod tests {
         ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
         defmt::assert!(buf.iter().all(|&b| b == 0xFF));
     }
+
+    /// Decode the JEDEC Basic Flash Parameter Table and check it against the expected part.
+    #[test]
+    async fn sfdp_basic_flash_parameters(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        let header = memory.read_sfdp_header().unwrap();
+        defmt::assert!(header.parameter_headers() >= 1);
+
+        let params = memory.read_basic_flash_parameters().unwrap();
+        defmt::assert_eq!(params.capacity(), 8 * 1024 * 1024);
+        defmt::assert_eq!(params.erase_4k_opcode, Some(0x20));
+        defmt::assert_eq!(params.fast_read.read_1_4_4.unwrap().opcode, 0xEB);
+        defmt::assert_eq!(params.page_size, Some(256));
+    }
+
+    /// Protect the top of the array and check the reported range, then remove the protection.
+    #[test]
+    async fn protect_top_range(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        const CAPACITY: u32 = 8 * 1024 * 1024;
+        // The smallest setting protects 1/64 of the array
+        let range = CAPACITY - CAPACITY / 64..CAPACITY;
+
+        memory.protect_range(range.clone()).unwrap();
+        memory.wait_wip().unwrap();
+        defmt::assert_eq!(memory.protected_range().unwrap(), range);
+
+        memory.protect_range(0..0).unwrap();
+        memory.wait_wip().unwrap();
+        defmt::assert!(memory.protected_range().unwrap().is_empty());
+    }
+
+    #[test]
+    async fn secure_otp_read(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        let mut array = [0; 16];
+        memory.read_fast(0, &mut array).unwrap();
+
+        let mut buff = [0; 16];
+        let mut otp = memory.secure_otp().unwrap();
+        otp.read(0, &mut buff).unwrap();
+        defmt::assert!(matches!(
+            otp.read(SECURE_OTP_SIZE - 8, &mut buff),
+            Err(Error::OutOfBounds)
+        ));
+        otp.exit().unwrap();
+
+        // Dropping the guard exits the OTP as well
+        let otp = memory.secure_otp().unwrap();
+        drop(otp);
+        memory.read_fast(0, &mut buff).unwrap();
+        defmt::assert_eq!(buff, array);
+    }
+
+    #[test]
+    async fn deep_power_down_wake(
+        memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        let sleeping = memory.deep_power_down().ok().unwrap();
+        let mut memory = sleeping.wake().ok().unwrap();
+
+        let (manufacturer, _, _) = memory.read_identification().unwrap();
+        defmt::assert_eq!(manufacturer.0, 0xC2);
+    }
+
+    #[test]
+    async fn pending_operation_refuses_conflicts(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        let erase = memory.erase_sector(0).unwrap();
+        defmt::assert!(matches!(memory.write_page(0, &[42]), Err(Error::Busy)));
+
+        while let Err(Error::Busy) = erase.poll(&mut memory) {}
+        erase.poll(&mut memory).unwrap();
+        memory
+            .write_page(0, &[42])
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
+    }
+
+    #[test]
+    async fn read_while_erasing(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        let mut expected = [0; 16];
+        memory.read_fast(0, &mut expected).unwrap();
+
+        let mut buff = [0; 16];
+        let erase = memory.erase_block64(BLOCK64_SIZE).unwrap();
+        memory.read_while_erasing(0, &mut buff).unwrap();
+        defmt::assert_eq!(buff, expected);
+        defmt::assert!(matches!(
+            memory.read_while_erasing(BLOCK64_SIZE, &mut buff),
+            Err(Error::Busy)
+        ));
+        erase.wait(&mut memory).unwrap();
+    }
+
+    #[test]
+    async fn read_wrapped(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        let pattern: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+        memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
+        memory
+            .write_page(0, &pattern)
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
+
+        let mut buff = [0; 8];
+        defmt::assert!(matches!(
+            memory.read_wrapped(4, &mut buff),
+            Err(Error::Value)
+        ));
+        memory.set_burst_length(BurstLength::Wrap8).unwrap();
+        memory.read_wrapped(4, &mut buff).unwrap();
+        defmt::assert_eq!(buff, [4, 5, 6, 7, 0, 1, 2, 3]);
+        memory.set_burst_length(BurstLength::Disabled).unwrap();
+    }
+
+    #[test]
+    async fn managed_power_mode(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        memory.set_power_mode(PowerMode::UltraLowPower).unwrap();
+        memory.set_power_management(Some(PowerThresholds {
+            read: 16,
+            write: 16,
+        }));
+
+        let pattern = [0x5A; 32];
+        let mut buff = [0; 32];
+        NorFlash::erase(&mut memory, 0, SECTOR_SIZE).unwrap();
+        NorFlash::write(&mut memory, 0, &pattern).unwrap();
+        ReadNorFlash::read(&mut memory, 0, &mut buff).unwrap();
+        defmt::assert_eq!(buff, pattern);
+        defmt::assert_eq!(memory.power_mode(), Some(PowerMode::UltraLowPower));
+        defmt::assert_eq!(
+            memory.read_configuration().unwrap().power_mode,
+            PowerMode::UltraLowPower
+        );
+    }
+
+    #[test]
+    async fn trait_erase_unaligned_blocks(
+        mut memory: MX25R6435F<ExclusiveDevice<Spim<'static, SPI3>, Output<'static>, Delay>, Delay>,
+    ) {
+        const FROM: u32 = SECTOR_SIZE;
+        const TO: u32 = SECTOR_SIZE + BLOCK64_SIZE;
+
+        // 7 sectors up to the first 32K block, the 32K block and the last sector
+        let plan = ErasePlan::new(FROM, TO).unwrap();
+        defmt::assert_eq!(plan.count(), 9);
+
+        NorFlash::erase(&mut memory, FROM, TO).unwrap();
+        let mut buf = [0u8; 16];
+        ReadNorFlash::read(&mut memory, TO - 16, &mut buf).unwrap();
+        defmt::assert!(buf.iter().all(|&b| b == 0xFF));
+    }
 }
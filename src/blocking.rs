@@ -1,275 +1,652 @@
+use core::ops::Range;
+
 use crate::{
+    bus::{BusWidth, FlashBus, Instruction, ReadMode},
     command::Command,
+    erase::EraseStep,
     error::Error,
+    fmt::trace,
+    part::Part,
+    poll::FixedInterval,
+    power::PowerThresholds,
+    protection::BlockProtection,
+    protocol::{
+        page_chunks, Completion, Io, Outcome, Protect, Protocol, ReadWhileErasing, Sequence,
+        SetPowerMode, Step,
+    },
     register::*,
-    {BLOCK64_SIZE, SECTOR_SIZE},
+    sfdp::*,
+    timing::{Operation, Timings},
+    {DEEP_POWER_DOWN_RECOVERY_US, DYNAMIC_SIZE},
 };
-use bit::BitIndex;
-use embedded_hal::spi::Operation;
-use embedded_hal::spi::SpiDevice;
+use embedded_hal::delay::DelayNs;
 
 /// Type alias for the MX25R512F
-pub type MX25R512F<SPI> = MX25R<0x00FFFF, SPI>;
+pub type MX25R512F<SPI, D> = MX25R<0x00FFFF, SPI, D>;
 
 /// Type alias for the MX25R1035F
-pub type MX25R1035F<SPI> = MX25R<0x01FFFF, SPI>;
+pub type MX25R1035F<SPI, D> = MX25R<0x01FFFF, SPI, D>;
 
 /// Type alias for the MX25R2035F
-pub type MX25R2035F<SPI> = MX25R<0x03FFFF, SPI>;
+pub type MX25R2035F<SPI, D> = MX25R<0x03FFFF, SPI, D>;
 
 /// Type alias for the MX25R4035F
-pub type MX25R4035F<SPI> = MX25R<0x07FFFF, SPI>;
+pub type MX25R4035F<SPI, D> = MX25R<0x07FFFF, SPI, D>;
 
 /// Type alias for the MX25R8035F
-pub type MX25R8035F<SPI> = MX25R<0x0FFFFF, SPI>;
+pub type MX25R8035F<SPI, D> = MX25R<0x0FFFFF, SPI, D>;
 
 /// Type alias for the MX25R1635F
-pub type MX25R1635F<SPI> = MX25R<0x1FFFFF, SPI>;
+pub type MX25R1635F<SPI, D> = MX25R<0x1FFFFF, SPI, D>;
 
 /// Type alias for the MX25R3235F
-pub type MX25R3235F<SPI> = MX25R<0x3FFFFF, SPI>;
+pub type MX25R3235F<SPI, D> = MX25R<0x3FFFFF, SPI, D>;
 
 /// Type alias for the MX25R6435F
-pub type MX25R6435F<SPI> = MX25R<0x7FFFFF, SPI>;
+pub type MX25R6435F<SPI, D> = MX25R<0x7FFFFF, SPI, D>;
+
+/// Type alias for a MX25R whose capacity is only known at runtime, see [`MX25R::detect`]
+pub type DynamicMX25R<SPI, D> = MX25R<DYNAMIC_SIZE, SPI, D>;
 
 /// The generic low level MX25R driver
-pub struct MX25R<const SIZE: u32, SPI>
+pub struct MX25R<const SIZE: u32, SPI, D>
 where
-    SPI: SpiDevice,
+    SPI: FlashBus,
+    D: DelayNs,
 {
     spi: SPI,
+    protocol: Protocol,
+    delay: D,
 }
 
-impl<const SIZE: u32, SPI, E> MX25R<SIZE, SPI>
+impl<const SIZE: u32, SPI, D, E> MX25R<SIZE, SPI, D>
 where
-    SPI: SpiDevice<Error = E>,
+    SPI: FlashBus<Error = E>,
+    D: DelayNs,
 {
+    /// Capacity of the chip in bytes, meaningless for the dynamic driver
+    #[deprecated(
+        note = "meaningless for the dynamic driver, use `capacity` which works for every driver"
+    )]
     pub const CAPACITY: usize = SIZE as usize + 1;
 
-    pub fn new(spi: SPI) -> Self {
-        Self { spi }
+    /// Create a driver for a part known at compile time.
+    /// Use [`Self::detect`] or [`Self::new_with_part`] for the runtime sized driver.
+    pub fn new(spi: SPI, delay: D) -> Self {
+        const {
+            assert!(
+                SIZE != DYNAMIC_SIZE,
+                "the dynamic driver must be created with `detect` or `new_with_part`"
+            )
+        };
+        Self {
+            spi,
+            protocol: Protocol::new(SIZE),
+            delay,
+        }
+    }
+
+    /// Capacity of the chip in bytes
+    pub fn capacity(&self) -> usize {
+        self.protocol.capacity()
+    }
+
+    /// The part driven, if the capacity matches one of the MX25R series
+    pub fn part(&self) -> Option<Part> {
+        self.protocol.part()
     }
 
-    /// Read the wip bit, just less noisy than the `read_status().unwrap().wip_bit`
+    /// Maximum durations of the operations, the limits of the largest part are used if the capacity doesn't match a part
+    pub fn timings(&self) -> Timings {
+        self.protocol.timings()
+    }
+
+    /// Typical durations of the operations, to estimate the duration of an [`ErasePlan`](crate::erase::ErasePlan)
+    pub fn typical_timings(&self) -> Timings {
+        self.protocol.typical_timings()
+    }
+
+    /// Read the wip bit, just less noisy than the `read_status().unwrap().wip_bit`.
+    /// With the failure detection enabled, the failure of the program or erase found completed is reported,
+    /// see [`Self::set_failure_detection`]
     pub fn poll_wip(&mut self) -> Result<(), Error<E>> {
-        if self.read_status()?.wip_bit {
-            return Err(Error::Busy);
+        // The status can be read in the secured OTP, the pending exit isn't flushed so that the flush can wait
+        let mut status = [0];
+        self.bus_read_unflushed(Command::ReadStatus.instruction(), &mut status)?;
+        if self.protocol.status_polled(status[0].into())? {
+            let mut security = [0];
+            self.bus_read_unflushed(Command::ReadSecurityRegister.instruction(), &mut security)?;
+            self.protocol.failure_checked(security[0].into())?;
         }
         Ok(())
     }
 
+    /// Wait for the ongoing operation to complete, polling the wip bit in between delays.
+    /// Returns [`Error::Timeout`] if the chip is still busy after the maximum duration of the operation, or of a register
+    /// write if no operation is known to be in progress, see [`Self::timings`].
+    /// Only the delays count towards that limit, the time spent polling on the bus comes on top
     pub fn wait_wip(&mut self) -> Result<(), Error<E>> {
+        let mut wait = self.protocol.wait();
+        let mut interval = FixedInterval(wait.interval_us());
         loop {
-            let res = self.poll_wip();
-            match res {
+            match self.poll_wip() {
                 Ok(()) => return Ok(()),
-                Err(Error::Busy) => continue,
-                err @ Err(_) => return err,
+                Err(Error::Busy) => {
+                    let delay_us = wait.busy(&mut interval)?;
+                    self.delay.delay_us(delay_us);
+                }
+                Err(e) => return Err(e),
             }
         }
     }
 
-    pub fn verify_addr(addr: u32) -> Result<u32, Error<E>> {
-        if addr > SIZE {
-            return Err(Error::OutOfBounds);
-        }
-        Ok(addr)
+    pub fn verify_addr(&self, addr: u32) -> Result<u32, Error<E>> {
+        self.protocol.verify_addr(addr)
     }
 
-    fn command_write(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
-        self.spi.write(bytes).map_err(Error::Spi)
+    fn bus_read(&mut self, instruction: Instruction, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.flush_secure_otp_exit()?;
+        self.bus_read_unflushed(instruction, buff)
     }
-    fn command_transfer(&mut self, bytes: &mut [u8]) -> Result<(), Error<E>> {
-        self.spi.transfer_in_place(bytes).map_err(Error::Spi)
+
+    fn bus_write(&mut self, instruction: Instruction, buff: &[u8]) -> Result<(), Error<E>> {
+        self.flush_secure_otp_exit()?;
+        self.bus_write_unflushed(instruction, buff)
     }
 
-    fn addr_command(&mut self, addr: u32, cmd: Command) -> Result<(), Error<E>> {
-        let addr_val = Self::verify_addr(addr)?;
-        let cmd: [u8; 4] = [
-            cmd as u8,
-            (addr_val >> 16) as u8,
-            (addr_val >> 8) as u8,
-            addr_val as u8,
-        ];
-        self.spi.write(&cmd).map_err(Error::Spi)
+    fn bus_read_unflushed(
+        &mut self,
+        instruction: Instruction,
+        buff: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        if !self.spi.supports(instruction.max_width()) {
+            return Err(Error::Unsupported);
+        }
+        self.spi.read(&instruction, buff).map_err(Error::Spi)
     }
 
-    fn write_read_base(&mut self, write: &[u8], read: &mut [u8]) -> Result<(), Error<E>> {
-        self.spi
-            .transaction(&mut [Operation::Write(write), Operation::Read(read)])
-            .map_err(Error::Spi)
+    fn bus_write_unflushed(
+        &mut self,
+        instruction: Instruction,
+        buff: &[u8],
+    ) -> Result<(), Error<E>> {
+        if !self.spi.supports(instruction.max_width()) {
+            return Err(Error::Unsupported);
+        }
+        self.spi.write(&instruction, buff).map_err(Error::Spi)
     }
 
-    fn read_base(&mut self, addr: u32, cmd: Command, buff: &mut [u8]) -> Result<(), Error<E>> {
-        self.wait_wip()?;
-        let addr_val = Self::verify_addr(addr)?;
-        let cmd: [u8; 4] = [
-            cmd as u8,
-            (addr_val >> 16) as u8,
-            (addr_val >> 8) as u8,
-            addr_val as u8,
-        ];
-
-        let res = self.write_read_base(&cmd, buff);
-        #[cfg(feature = "defmt")]
-        if res.is_ok() {
-            defmt::trace!("Read from {=u32}, {=usize}: {:?}", addr, buff.len(), buff);
-        } else {
-            defmt::trace!("Failed to read");
+    /// Exit the secured OTP left by a dropped guard, once the ongoing operation completes.
+    fn flush_secure_otp_exit(&mut self) -> Result<(), Error<E>> {
+        if !self.protocol.otp_exit_needed() {
+            return Ok(());
         }
-        res
+        // Neither the wait nor the exit go through the flushing bus helpers, as in the async driver where the call
+        // graph must not be recursive
+        let res = self
+            .wait_wip()
+            .and_then(|()| self.bus_write_unflushed(Command::ExitSecureOTP.instruction(), &[]));
+        self.protocol.otp_exit_flushed(res)
     }
 
-    fn read_base_dummy(
+    /// Run the steps of `sequence` until it completes, `buff` is the buffer of its reads
+    fn run<S: Sequence>(
         &mut self,
-        addr: u32,
-        cmd: Command,
+        mut sequence: S,
         buff: &mut [u8],
-    ) -> Result<(), Error<E>> {
-        let addr_val = Self::verify_addr(addr)?;
+    ) -> Result<S::Output, Error<E>> {
+        let mut error = None;
+        let mut outcome = Outcome::Start;
+        loop {
+            let res = match sequence.step(&mut self.protocol, outcome) {
+                Ok(Step::Io(io)) => self.io(io, buff),
+                Ok(Step::Done(output)) => return error.map_or(Ok(output), Err),
+                // Only after a failed step
+                Ok(Step::Abort) => return Err(error.unwrap_or(Error::Value)),
+                Err(e) => Err(e),
+            };
+            outcome = res.unwrap_or_else(|e| {
+                error.get_or_insert(e);
+                Outcome::Failed
+            });
+        }
+    }
+
+    /// Run a step of a [`Sequence`]
+    fn io(&mut self, io: Io, buff: &mut [u8]) -> Result<Outcome, Error<E>> {
+        match io {
+            Io::Send(command) => self.command_write(command, &[])?,
+            Io::ReadStatus => return self.read_status().map(Outcome::Status),
+            Io::ReadConfiguration => return self.read_configuration().map(Outcome::Configuration),
+            Io::ReadSecurity => return self.read_security_register().map(Outcome::Security),
+            Io::WriteStatus(status) => self.write_status(status)?,
+            Io::WriteStatusConfiguration(status, config) => {
+                self.write_status_configuration(status, config)?
+            }
+            Io::Poll => match self.poll_wip() {
+                Err(Error::Busy) => return Ok(Outcome::Busy),
+                res => res?,
+            },
+            Io::Wait => self.wait_wip()?,
+            Io::Delay(delay_us) => self.delay.delay_us(delay_us),
+            Io::Read(mode, addr) => self.read_instruction(mode, addr, buff)?,
+        }
+        Ok(Outcome::Completed)
+    }
+
+    fn command_write(&mut self, cmd: Command, bytes: &[u8]) -> Result<(), Error<E>> {
+        self.bus_write(cmd.instruction(), bytes)
+    }
+
+    fn command_read(&mut self, cmd: Command, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        self.bus_read(cmd.instruction(), bytes)
+    }
+
+    /// Send a read instruction built by the protocol, once the ongoing operation completes
+    fn read_base(&mut self, instruction: Instruction, buff: &mut [u8]) -> Result<(), Error<E>> {
         self.wait_wip()?;
 
-        let cmd: [u8; 5] = [
-            cmd as u8,
-            (addr_val >> 16) as u8,
-            (addr_val >> 8) as u8,
-            addr_val as u8,
-            Command::Dummy as u8,
-        ];
-        let res = self.write_read_base(&cmd, buff);
-        #[cfg(feature = "defmt")]
+        let res = self.bus_read(instruction, buff);
+        #[cfg(any(feature = "defmt", feature = "log"))]
         if res.is_ok() {
-            defmt::trace!("Read from {=u32}, {=usize}: {:?}", addr, buff.len(), buff);
+            trace!(
+                "Read from {}, {}: {:?}",
+                instruction.address.unwrap_or(0),
+                buff.len(),
+                buff
+            );
         } else {
-            defmt::trace!("Failed to read");
+            trace!("Failed to read");
         }
         res
     }
 
-    fn write_base(&mut self, addr: u32, cmd: Command, buff: &[u8]) -> Result<(), Error<E>> {
-        let addr_val: u32 = Self::verify_addr(addr)?;
-        let cmd: [u8; 4] = [
-            cmd as u8,
-            (addr_val >> 16) as u8,
-            (addr_val >> 8) as u8,
-            addr_val as u8,
-        ];
-
-        let res = self
-            .spi
-            .transaction(&mut [Operation::Write(&cmd), Operation::Write(buff)])
-            .map_err(Error::Spi);
+    fn write_base(&mut self, instruction: Instruction, buff: &[u8]) -> Result<(), Error<E>> {
+        let res = self.bus_write(instruction, buff);
 
-        #[cfg(feature = "defmt")]
+        #[cfg(any(feature = "defmt", feature = "log"))]
         if res.is_ok() {
-            defmt::trace!("write from {=u32}, {=usize}: {:?}", addr, buff.len(), buff);
+            trace!(
+                "Write from {}, {}: {:?}",
+                instruction.address.unwrap_or(0),
+                buff.len(),
+                buff
+            );
         } else {
-            defmt::trace!("Failed to write");
+            trace!("Failed to write");
         }
         res
     }
 
+    /// Read with the instruction of `mode`, reading the configuration register or setting the quad enable bit first if needed
+    fn read_instruction(
+        &mut self,
+        mode: ReadMode,
+        addr: u32,
+        buff: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        if self.protocol.needs_quad_enable(mode.width()) {
+            self.ensure_quad_enabled()?;
+        }
+        if self.protocol.needs_dummy_cycle(mode) {
+            self.read_configuration()?;
+        }
+        let instruction = self.protocol.read_instruction(mode, addr)?;
+        self.read_base(instruction, buff)
+    }
+
+    /// Set the quad enable bit if needed, required before any quad instruction
+    fn ensure_quad_enabled(&mut self) -> Result<(), Error<E>> {
+        if !self.protocol.needs_quad_enable(BusWidth::Quad) {
+            return Ok(());
+        }
+        if !self.spi.supports(BusWidth::Quad) {
+            return Err(Error::Unsupported);
+        }
+        let mut status = self.read_status()?;
+        if !status.quad_enable {
+            status.quad_enable = true;
+            self.write_status(status)?;
+            self.wait_wip()?;
+        }
+        self.protocol.quad_enabled = true;
+        Ok(())
+    }
+
+    /// Wait for the chip to be ready, refused with [`Error::Busy`] while the operation of a token is in progress
+    fn wait_ready(&mut self) -> Result<(), Error<E>> {
+        if self.protocol.token_pending() {
+            self.poll_wip()?;
+        }
+        self.wait_wip()
+    }
+
     fn prepare_write(&mut self) -> Result<(), Error<E>> {
-        self.wait_wip()?;
+        self.wait_ready()?;
         self.write_enable()
     }
 
     /// Read n bytes from an addresss, note that you should maybe use [`Self::read_fast`] instead
     pub fn read(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
-        self.read_base(addr, Command::Read, buff)
+        self.read_instruction(ReadMode::Normal, addr, buff)
     }
 
     /// Read n bytes quickly from an address
     pub fn read_fast(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
-        self.read_base_dummy(addr, Command::ReadF, buff)
+        self.read_instruction(ReadMode::Fast, addr, buff)
     }
 
-    /// Write n bytes to a page. [`Self::write_enable`] is called internally
-    pub fn write_page(&mut self, addr: u32, buff: &[u8]) -> Result<(), Error<E>> {
-        self.prepare_write()?;
-        self.write_base(addr, Command::ProgramPage, buff)
+    /// Read n bytes using the dual output instruction (DREAD), the data is received on 2 lines
+    pub fn read_dual_output(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.read_instruction(ReadMode::DualOutput, addr, buff)
+    }
+
+    /// Read n bytes using the dual I/O instruction (2READ), the address and data are sent on 2 lines
+    pub fn read_dual_io(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.read_instruction(ReadMode::DualIo, addr, buff)
+    }
+
+    /// Read n bytes using the quad output instruction (QREAD), the data is received on 4 lines.
+    /// The quad enable bit is set if needed
+    pub fn read_quad_output(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.read_instruction(ReadMode::QuadOutput, addr, buff)
+    }
+
+    /// Read n bytes using the quad I/O instruction (4READ), the address and data are sent on 4 lines.
+    /// The quad enable bit is set if needed
+    pub fn read_quad_io(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.read_instruction(ReadMode::QuadIo, addr, buff)
+    }
+
+    /// The read instruction used by the `ReadNorFlash` implementation
+    pub fn read_mode(&self) -> ReadMode {
+        self.protocol.read_mode
     }
 
-    /// Erase a 4kB sector. [`Self::write_enable`] is called internally
-    pub fn erase_sector(&mut self, addr: u32) -> Result<(), Error<E>> {
-        if addr % SECTOR_SIZE != 0 {
-            return Err(Error::NotAligned);
+    /// Select the read instruction used by the `ReadNorFlash` implementation, [`ReadMode::Fast`] by default.
+    /// Returns [`Error::Unsupported`] if the bus can't use the lines required by the instruction
+    pub fn set_read_mode(&mut self, mode: ReadMode) -> Result<(), Error<E>> {
+        if !self.spi.supports(mode.width()) {
+            return Err(Error::Unsupported);
         }
-        self.prepare_write()?;
-        self.addr_command(addr, Command::SectorErase)?;
-        #[cfg(feature = "defmt")]
-        defmt::trace!("Erase sector {:?}", addr);
+        self.protocol.read_mode = mode;
         Ok(())
     }
 
-    /// Erase a 64kB block. [`Self::write_enable`] is called internally
-    pub fn erase_block64(&mut self, addr: u32) -> Result<(), Error<E>> {
-        if addr % BLOCK64_SIZE != 0 {
-            return Err(Error::NotAligned);
-        }
+    /// If the failure detection is enabled, see [`Self::set_failure_detection`]
+    pub fn failure_detection(&self) -> bool {
+        self.protocol.failure_detection
+    }
+
+    /// Enable the failure detection. Once enabled, the security register is read when a program or erase is found
+    /// completed, and its failure is reported as [`Error::ProgramFailed`] or [`Error::EraseFailed`] by
+    /// [`PendingOperation::poll`] or [`PendingOperation::wait`]. If the completion is first seen by another call,
+    /// e.g. the next program or a read, that call reports the failure and the token reports it again
+    pub fn set_failure_detection(&mut self, enabled: bool) {
+        self.protocol.failure_detection = enabled;
+    }
+
+    /// Read n bytes using the instruction selected with [`Self::set_read_mode`]
+    pub fn read_with_mode(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.read_instruction(self.protocol.read_mode, addr, buff)
+    }
+
+    /// Read n bytes wrapping around the aligned window of the current burst length, with the instruction selected with [`Self::set_read_mode`].
+    /// The read starts at `addr` and continues from the start of the window once its end is reached, e.g. to fill a cache line critical word first.
+    /// Returns [`Error::Value`] if the wrap around is disabled, see [`Self::set_burst_length`]
+    pub fn read_wrapped(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.protocol.check_wrapped()?;
+        self.read_with_mode(addr, buff)
+    }
+
+    /// Read n bytes with [`Self::read_with_mode`] while an erase is in progress.
+    /// The erase is suspended for the read and resumed afterwards, even if the read fails. Reads overlapping the range being erased are refused with [`Error::Busy`].
+    /// Without an erase in progress, this is the same as [`Self::read_with_mode`]
+    pub fn read_while_erasing(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        self.run(ReadWhileErasing::new(addr, buff.len()), buff)
+    }
+
+    /// Write n bytes to a page. [`Self::write_enable`] is called internally.
+    /// Returns a token to wait for the program, other programs and erases are refused with [`Error::Busy`] until it completes
+    pub fn write_page(&mut self, addr: u32, buff: &[u8]) -> Result<PendingOperation, Error<E>> {
+        let instruction = self.protocol.program_instruction(addr, BusWidth::Single)?;
+        self.program(instruction, buff)
+    }
+
+    /// Write n bytes to a page using the quad page program instruction (4PP), the address and data are sent on 4 lines.
+    /// The write enable and the quad enable bit are handled internally, the program is waited for with the returned token
+    pub fn write_page_quad(
+        &mut self,
+        addr: u32,
+        buff: &[u8],
+    ) -> Result<PendingOperation, Error<E>> {
+        let instruction = self.protocol.program_instruction(addr, BusWidth::Quad)?;
+        self.ensure_quad_enabled()?;
+        self.program(instruction, buff)
+    }
+
+    fn program(
+        &mut self,
+        instruction: Instruction,
+        buff: &[u8],
+    ) -> Result<PendingOperation, Error<E>> {
         self.prepare_write()?;
-        self.addr_command(addr, Command::BlockErase)?;
-        #[cfg(feature = "defmt")]
-        defmt::trace!("Erase block 64 {:?}", addr);
-        Ok(())
+        self.write_base(instruction, buff)?;
+        Ok(self.started(Operation::Program, instruction.address.unwrap_or(0)))
     }
 
-    /// Erase a 32kB block. [`Self::write_enable`] is called internally
-    pub fn erase_block32(&mut self, addr: u32) -> Result<(), Error<E>> {
-        if addr % SECTOR_SIZE != 0 {
-            return Err(Error::NotAligned);
-        }
+    /// Erase a 4kB sector. [`Self::write_enable`] is called internally, the erase is waited for with the returned token
+    pub fn erase_sector(&mut self, addr: u32) -> Result<PendingOperation, Error<E>> {
+        self.erase_step(EraseStep::Sector(addr))
+    }
+
+    /// Erase a 64kB block. [`Self::write_enable`] is called internally, the erase is waited for with the returned token
+    pub fn erase_block64(&mut self, addr: u32) -> Result<PendingOperation, Error<E>> {
+        self.erase_step(EraseStep::Block64(addr))
+    }
+
+    /// Erase a 32kB block. [`Self::write_enable`] is called internally, the erase is waited for with the returned token
+    pub fn erase_block32(&mut self, addr: u32) -> Result<PendingOperation, Error<E>> {
+        self.erase_step(EraseStep::Block32(addr))
+    }
+
+    /// Run a step of an [`ErasePlan`](crate::erase::ErasePlan), the erase is waited for with the returned token
+    pub fn erase_step(&mut self, step: EraseStep) -> Result<PendingOperation, Error<E>> {
+        let instruction = self.protocol.erase_instruction(step)?;
         self.prepare_write()?;
-        self.addr_command(addr, Command::BlockErase32)?;
-        #[cfg(feature = "defmt")]
-        defmt::trace!("Erase block 32 {:?}", addr);
-        Ok(())
+        self.bus_write(instruction, &[])?;
+        trace!("Erase {:?}", step);
+        Ok(self.started(step.operation(), step.addr()))
     }
 
-    /// Erase the whole chip. [`Self::write_enable`] is called internally
-    pub fn erase_chip(&mut self) -> Result<(), Error<E>> {
+    /// Erase the whole chip. [`Self::write_enable`] is called internally, the erase is waited for with the returned token
+    pub fn erase_chip(&mut self) -> Result<PendingOperation, Error<E>> {
         self.prepare_write()?;
-        self.command_write(&[Command::ChipErase as u8])?;
-        #[cfg(feature = "defmt")]
-        defmt::trace!("Erase chip");
-        Ok(())
+        self.command_write(Command::ChipErase, &[])?;
+        trace!("Erase chip");
+        Ok(self.started(Operation::ChipErase, 0))
+    }
+
+    fn started(&mut self, operation: Operation, addr: u32) -> PendingOperation {
+        self.protocol.started(operation, addr);
+        PendingOperation::new(operation, addr)
     }
 
     /// Read using the Serial Flash Discoverable Parameter instruction
     pub fn read_sfdp(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
-        self.read_base_dummy(addr, Command::ReadSfdp, buff)
+        let instruction = self.protocol.sfdp_instruction(addr)?;
+        self.read_base(instruction, buff)
+    }
+
+    /// Read and decode the SFDP header
+    pub fn read_sfdp_header(&mut self) -> Result<SfdpHeader, Error<E>> {
+        let mut buff = [0; HEADER_SIZE];
+        self.read_sfdp(0, &mut buff)?;
+        SfdpHeader::from_bytes(&buff).ok_or(Error::Sfdp)
+    }
+
+    /// Read the SFDP parameter header at `index`, up to [`SfdpHeader::last_parameter_header`]
+    pub fn read_sfdp_parameter_header(&mut self, index: u8) -> Result<ParameterHeader, Error<E>> {
+        let mut buff = [0; HEADER_SIZE];
+        self.read_sfdp(SfdpHeader::parameter_header_address(index), &mut buff)?;
+        Ok(ParameterHeader::from_bytes(&buff))
+    }
+
+    /// Locate, read and decode the JEDEC Basic Flash Parameter Table
+    pub fn read_basic_flash_parameters(&mut self) -> Result<BasicFlashParameters, Error<E>> {
+        let header = self.read_sfdp_header()?;
+        for index in 0..=header.last_parameter_header {
+            let param = self.read_sfdp_parameter_header(index)?;
+            if !param.is_basic_flash_parameters() {
+                continue;
+            }
+
+            let len = Protocol::basic_flash_parameters_len(&param);
+            let mut buff = [0; BASIC_FLASH_PARAMETER_MAX_DWORDS * 4];
+            self.read_sfdp(param.pointer, &mut buff[..len])?;
+            return BasicFlashParameters::from_bytes(&buff[..len]).ok_or(Error::Sfdp);
+        }
+        Err(Error::Sfdp)
     }
 
     /// Enable write operation, though you shouldn't need this function since it's already handled in the write/erase operations.
     fn write_enable(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::WriteEnable as u8])
+        self.command_write(Command::WriteEnable, &[])
     }
 
     /// Disable write
     pub fn write_disable(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::WriteDisable as u8])
+        self.command_write(Command::WriteDisable, &[])
     }
 
     /// Read the status register
     pub fn read_status(&mut self) -> Result<StatusRegister, Error<E>> {
-        let mut command: [u8; 2] = [Command::ReadStatus as u8, 0];
-
-        self.command_transfer(&mut command)?;
-        Ok(command[1].into())
+        let mut status = [0];
+        self.command_read(Command::ReadStatus, &mut status)?;
+        Ok(status[0].into())
     }
 
     /// Read the configuration register
     pub fn read_configuration(&mut self) -> Result<ConfigurationRegister, Error<E>> {
-        let mut command: [u8; 3] = [Command::ReadConfig as u8, 0, 0];
-        self.command_transfer(&mut command)?;
-        Ok(ConfigurationRegister {
-            dummmy_cycle: command[1].bit(6),
-            protected_section: command[1].bit(3).into(),
-            power_mode: command[2].bit(1).into(),
+        let mut config = [0; 2];
+        self.command_read(Command::ReadConfig, &mut config)?;
+        Ok(self.protocol.configuration_read(config))
+    }
+
+    /// Write the status register, the configuration register is left untouched.
+    /// The write enable is sent internally
+    pub fn write_status(&mut self, status: StatusRegister) -> Result<(), Error<E>> {
+        // Writing only the first byte leaves the configuration register untouched
+        let registers = Protocol::encode_status(status)?;
+        self.prepare_write()?;
+        self.command_write(Command::WriteStatus, &registers)?;
+        self.protocol.status_written(status);
+        Ok(())
+    }
+
+    /// Write both the status and configuration registers.
+    /// The write enable is sent internally
+    pub fn write_status_configuration(
+        &mut self,
+        status: StatusRegister,
+        config: ConfigurationRegister,
+    ) -> Result<(), Error<E>> {
+        let registers = Protocol::encode_status_configuration(status, config)?;
+        self.prepare_write()?;
+        self.command_write(Command::WriteStatus, &registers)?;
+        self.protocol.status_configuration_written(status, config);
+        Ok(())
+    }
+
+    /// Read the status register, apply `f` and write it back. Returns the written value
+    pub fn modify_status<F>(&mut self, f: F) -> Result<StatusRegister, Error<E>>
+    where
+        F: FnOnce(&mut StatusRegister),
+    {
+        let mut status = self.read_status()?;
+        f(&mut status);
+        self.write_status(status)?;
+        Ok(status)
+    }
+
+    /// Read the configuration register, apply `f` and write it back, the status register is preserved.
+    /// Returns the written value
+    pub fn modify_configuration<F>(&mut self, f: F) -> Result<ConfigurationRegister, Error<E>>
+    where
+        F: FnOnce(&mut ConfigurationRegister),
+    {
+        let status = self.read_status()?;
+        let mut config = self.read_configuration()?;
+        f(&mut config);
+        self.write_status_configuration(status, config)?;
+        Ok(config)
+    }
+
+    /// Last known power mode, `None` until the configuration register is read or written.
+    /// The SPI clock must be capped accordingly, see the datasheet
+    pub fn power_mode(&self) -> Option<PowerMode> {
+        self.protocol.power_mode
+    }
+
+    /// Switch the power mode and wait for the configuration register to be written
+    pub fn set_power_mode(&mut self, mode: PowerMode) -> Result<(), Error<E>> {
+        self.run(SetPowerMode::new(mode), &mut [])
+    }
+
+    /// The thresholds of the managed power mode, `None` if disabled
+    pub fn power_management(&self) -> Option<PowerThresholds> {
+        self.protocol.power_thresholds
+    }
+
+    /// Enable the managed power mode with the given thresholds, or disable it with `None`.
+    /// When enabled, the `NorFlash` reads and writes reaching the thresholds switch the chip to the
+    /// high performance mode and back to the ultra low power mode once done.
+    /// A chip already in the high performance mode is left untouched
+    pub fn set_power_management(&mut self, thresholds: Option<PowerThresholds>) {
+        self.protocol.power_thresholds = thresholds;
+    }
+
+    /// Read the block protection setting from the status and configuration registers
+    pub fn read_block_protection(&mut self) -> Result<BlockProtection, Error<E>> {
+        let status = self.read_status()?;
+        let config = self.read_configuration()?;
+        Ok(BlockProtection {
+            protected_block: status.protected_block,
+            area: config.protected_section,
         })
     }
 
+    /// Address range currently protected against program and erase, empty if nothing is protected
+    pub fn protected_range(&mut self) -> Result<Range<u32>, Error<E>> {
+        Ok(self.read_block_protection()?.range(self.capacity()))
+    }
+
+    /// Protect the smallest area covering `range` against program and erase, an empty range removes the protection.
+    /// The area starts from the end of the array selected by the top/bottom bit, which is left untouched.
+    /// Returns [`Error::Value`] if the range is beyond the capacity, or if only the whole chip covers it from that end:
+    /// select the other end with [`Self::protect_range_in`], or give the range of the whole chip to protect it all.
+    /// See [`BlockProtection::for_range`]
+    pub fn protect_range(&mut self, range: Range<u32>) -> Result<BlockProtection, Error<E>> {
+        self.run(Protect::new(range, None), &mut [])
+    }
+
+    /// Protect the smallest area at the top or bottom of the array covering `range`, as selected by `area`.
+    /// Returns [`Error::Value`] if the range is beyond the capacity, if only the whole chip covers it from `area`, or if
+    /// `area` is the top once the bottom was selected.
+    ///
+    /// Note that selecting the bottom sets the top/bottom bit, this operation is **NON REVERSIBLE**
+    pub fn protect_range_in(
+        &mut self,
+        range: Range<u32>,
+        area: ProtectedArea,
+    ) -> Result<BlockProtection, Error<E>> {
+        self.run(Protect::new(range, Some(area)), &mut [])
+    }
+
     /// Write configuration to the configuration register. [`Self::write_enable`] is called internally
+    #[deprecated(
+        note = "overwrites both registers, use `modify_status`, `modify_configuration` or `write_status_configuration`"
+    )]
     pub fn write_configuration(
         &mut self,
         block_protected: u8,
@@ -279,192 +656,378 @@ where
         protected_section: ProtectedArea,
         power_mode: PowerMode,
     ) -> Result<(), Error<E>> {
-        if block_protected > 0x0F {
-            return Err(Error::Value);
-        }
-        self.prepare_write()?;
-        let mut command: [u8; 4] = [Command::WriteStatus as u8, 0, 0, 0];
-        command[1].set_bit_range(2..6, block_protected);
-        command[1].set_bit(6, quad_enable);
-        command[1].set_bit(7, status_write_disable);
-        command[2].set_bit(3, protected_section.into());
-        command[2].set_bit(6, dummy_cycle);
-        command[3].set_bit(1, power_mode.into());
-        self.command_write(&command)?;
-        Ok(())
+        let status = StatusRegister {
+            write_protect_disable: status_write_disable,
+            quad_enable,
+            protected_block: block_protected,
+            write_enable_latch: false,
+            wip_bit: false,
+        };
+        let config = ConfigurationRegister {
+            dummmy_cycle: dummy_cycle,
+            protected_section,
+            power_mode,
+        };
+        self.write_status_configuration(status, config)
     }
 
     /// Suspend the pogram erase
     pub fn suspend_program_erase(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::ProgramEraseSuspend as u8])
+        self.command_write(Command::ProgramEraseSuspend, &[])?;
+        self.protocol.suspend_sent();
+        Ok(())
     }
 
     /// Resume program erase
     pub fn resume_program_erase(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::ProgramEraseResume as u8])
+        // Even if the resume is lost, the chip reports ready while suspended so the operation is soon forgotten
+        self.protocol.resumed();
+        self.command_write(Command::ProgramEraseResume, &[])
     }
 
-    /// Deep powerdown the chip
-    pub fn deep_power_down(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::DeepPowerDown as u8])
+    /// Deep powerdown the chip once the ongoing operation completes.
+    /// The chip ignores every command but the release while powered down, so the driver is only given back by [`MX25RSleeping::wake`].
+    /// On failure, the driver is returned with the error
+    pub fn deep_power_down(mut self) -> Result<MX25RSleeping<SIZE, SPI, D>, (Self, Error<E>)> {
+        if let Err(e) = self.wait_wip() {
+            return Err((self, e));
+        }
+        match self.command_write(Command::DeepPowerDown, &[]) {
+            Ok(()) => Ok(MX25RSleeping { flash: self }),
+            Err(e) => Err((self, e)),
+        }
     }
 
-    /// Set the burst length
-    pub fn set_burst_length(&mut self, burst_length: u8) -> Result<(), Error<E>> {
-        self.command_write(&[Command::SetBurstLength as u8, burst_length])
+    /// Set the wrap around length of the burst reads, see [`Self::read_wrapped`].
+    /// The chip ignores the instruction while busy, so like the programs it's refused with [`Error::Busy`] while a
+    /// token is pending, and sent once the chip is ready otherwise
+    pub fn set_burst_length(&mut self, burst_length: BurstLength) -> Result<(), Error<E>> {
+        self.wait_ready()?;
+        self.command_write(Command::SetBurstLength, &[burst_length.into()])?;
+        self.protocol.burst_length = burst_length;
+        Ok(())
+    }
+
+    /// The wrap around length of the burst reads
+    pub fn burst_length(&self) -> BurstLength {
+        self.protocol.burst_length
     }
 
     /// Read the identification of the device
     pub fn read_identification(
         &mut self,
     ) -> Result<(ManufacturerId, MemoryType, MemoryDensity), Error<E>> {
-        let mut command = [Command::ReadIdentification as u8, 0, 0, 0];
-        self.command_transfer(&mut command)?;
-        Ok((
-            ManufacturerId(command[1]),
-            MemoryType(command[2]),
-            MemoryDensity(command[3]),
-        ))
+        let mut id = [0; 3];
+        self.command_read(Command::ReadIdentification, &mut id)?;
+        Ok(Protocol::decode_identification(id))
     }
 
     /// Read the electronic signature of the device
     pub fn read_electronic_id(&mut self) -> Result<ElectronicId, Error<E>> {
-        let dummy = Command::Dummy as u8;
-        let mut command = [Command::ReadElectronicId as u8, dummy, dummy, dummy, 0];
-        self.command_transfer(&mut command)?;
-        Ok(ElectronicId(command[4]))
+        let mut id = [0];
+        self.bus_read(Protocol::electronic_id_instruction(), &mut id)?;
+        Ok(ElectronicId(id[0]))
     }
 
     /// Read the manufacturer ID and the device ID
     pub fn read_manufacturer_id(&mut self) -> Result<(ManufacturerId, DeviceId), Error<E>> {
-        let dummy = Command::Dummy as u8;
-        let mut command = [Command::ReadManufacturerId as u8, dummy, dummy, 0x00, 0, 0];
-        self.command_transfer(&mut command)?;
-        Ok((ManufacturerId(command[4]), DeviceId(command[5])))
+        let mut id = [0; 2];
+        self.bus_read(Protocol::manufacturer_id_instruction(), &mut id)?;
+        Ok((ManufacturerId(id[0]), DeviceId(id[1])))
     }
 
     /// Enter to access additionnal 8kB of secured memory,
     /// which is independent of the main array. Note that it cannot be updated once locked down. See [`Self::write_security_register`]
     pub fn enter_secure_opt(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::EnterSecureOTP as u8])
+        self.command_write(Command::EnterSecureOTP, &[])
     }
 
     /// Exit the secured OTP
     pub fn exit_secure_opt(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::ExitSecureOTP as u8])
+        self.command_write(Command::ExitSecureOTP, &[])
+    }
+
+    /// Enter the secured OTP and return a guard giving access to it, the OTP is exited when the guard is dropped.
+    /// See [`SecureOtp`]
+    pub fn secure_otp(&mut self) -> Result<SecureOtp<'_, SIZE, SPI, D>, Error<E>> {
+        self.wait_wip()?;
+        self.enter_secure_opt()?;
+        Ok(SecureOtp {
+            flash: self,
+            active: true,
+        })
     }
 
     /// Read the security register
     pub fn read_security_register(&mut self) -> Result<SecurityRegister, Error<E>> {
-        let mut command = [Command::ReadSecurityRegister as u8, 0];
-        self.command_transfer(&mut command)?;
-        Ok(SecurityRegister {
-            erase_failed: command[1].bit(6),
-            program_failed: command[1].bit(5),
-            erase_suspended: command[1].bit(3),
-            program_suspended: command[1].bit(2),
-            locked_down: command[1].bit(1),
-            secured_otp: command[1].bit(0),
-        })
+        let mut security = [0];
+        self.command_read(Command::ReadSecurityRegister, &mut security)?;
+        Ok(security[0].into())
     }
 
-    /// Write the security register, note that this operation is **NON REVERSIBLE**
+    /// Write the security register to lock down the secured OTP, note that this operation is **NON REVERSIBLE**.
+    /// The write enable is sent first, so like the programs it's refused with [`Error::Busy`] while a token is pending
     pub fn write_security_register(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::WriteSecurityRegister as u8])
+        self.prepare_write()?;
+        self.command_write(Command::WriteSecurityRegister, &[])?;
+        self.protocol.started(Operation::WriteRegister, 0);
+        Ok(())
     }
 
     /// No operation, can terminate a reset enabler
     pub fn nop(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::Nop as u8])
+        self.command_write(Command::Nop, &[])
     }
 
     /// Enable reset, though you shouldn't need this function since it's already handled in the reset operation.
     pub fn reset_enable(&mut self) -> Result<(), Error<E>> {
-        self.command_write(&[Command::ResetEnable as u8])
+        self.command_write(Command::ResetEnable, &[])
     }
 
     /// Reset the chip. [`Self::reset_enable`] is called internally
     pub fn reset(&mut self) -> Result<(), Error<E>> {
         self.reset_enable()?;
-        self.command_write(&[Command::ResetMemory as u8])
+        self.command_write(Command::ResetMemory, &[])?;
+        self.protocol.reset();
+        Ok(())
+    }
+}
+
+impl<SPI, D, E> MX25R<DYNAMIC_SIZE, SPI, D>
+where
+    SPI: FlashBus<Error = E>,
+    D: DelayNs,
+{
+    /// Create a driver for a part selected at runtime
+    pub fn new_with_part(spi: SPI, delay: D, part: Part) -> Self {
+        Self {
+            spi,
+            protocol: Protocol::new(part.last_address()),
+            delay,
+        }
+    }
+
+    /// Identify the chip with [`Self::read_identification`] and create a driver with the matching capacity.
+    /// Returns [`Error::UnknownDevice`] if the chip is not part of the MX25R series.
+    pub fn detect(spi: SPI, delay: D) -> Result<Self, Error<E>> {
+        // Start with the smallest part, the identification doesn't need an address
+        let mut flash = Self::new_with_part(spi, delay, Part::MX25R512F);
+        let (manufacturer, memory_type, density) = flash.read_identification()?;
+        let part = Part::from_identification(manufacturer, memory_type, density)
+            .ok_or(Error::UnknownDevice)?;
+        flash.protocol.size = part.last_address();
+        Ok(flash)
+    }
+}
+
+/// A program or erase in progress, returned by the program and erase functions of [`MX25R`].
+/// The driver refuses other programs and erases with [`Error::Busy`] until the operation completes
+#[must_use = "the operation must be polled or waited for before programming or erasing again"]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, PartialEq, Eq)]
+pub struct PendingOperation {
+    operation: Operation,
+    addr: u32,
+}
+
+impl PendingOperation {
+    fn new(operation: Operation, addr: u32) -> Self {
+        PendingOperation { operation, addr }
+    }
+
+    /// The operation in progress
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    /// Address of the operation, 0 for the chip erase
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+
+    /// Returns [`Error::Busy`] while the operation is in progress.
+    /// Once completed, the failure flags are checked if the failure detection is enabled.
+    /// Returns [`Error::Value`] if the token is stale, i.e. another program or erase was started since it completed
+    pub fn poll<const SIZE: u32, SPI, D, E>(
+        &self,
+        flash: &mut MX25R<SIZE, SPI, D>,
+    ) -> Result<(), Error<E>>
+    where
+        SPI: FlashBus<Error = E>,
+        D: DelayNs,
+    {
+        flash.run(Completion::poll(self.operation, self.addr), &mut [])
+    }
+
+    /// Wait for the operation to complete, see [`MX25R::wait_wip`].
+    /// The failure flags are checked if the failure detection is enabled, a stale token is refused as by [`Self::poll`]
+    pub fn wait<const SIZE: u32, SPI, D, E>(
+        self,
+        flash: &mut MX25R<SIZE, SPI, D>,
+    ) -> Result<(), Error<E>>
+    where
+        SPI: FlashBus<Error = E>,
+        D: DelayNs,
+    {
+        flash.run(Completion::wait(self.operation, self.addr), &mut [])
+    }
+}
+
+/// Scoped access to the secured OTP, created with [`MX25R::secure_otp`].
+/// While the guard lives, the reads and programs target the 8Kbit OTP instead of the main array.
+/// Dropping the guard exits the secured OTP once any program in progress completes. If the wait or the exit fails,
+/// the exit is retried before the next instruction sent by the driver, [`MX25R::poll_wip`] aside.
+/// Prefer [`Self::exit`] to handle the errors.
+pub struct SecureOtp<'a, const SIZE: u32, SPI, D>
+where
+    SPI: FlashBus,
+    D: DelayNs,
+{
+    flash: &'a mut MX25R<SIZE, SPI, D>,
+    active: bool,
+}
+
+impl<const SIZE: u32, SPI, D, E> SecureOtp<'_, SIZE, SPI, D>
+where
+    SPI: FlashBus<Error = E>,
+    D: DelayNs,
+{
+    /// Read n bytes from the OTP
+    pub fn read(&mut self, addr: u32, buff: &mut [u8]) -> Result<(), Error<E>> {
+        Protocol::check_otp(addr, buff.len())?;
+        self.flash.read_fast(addr, buff)
+    }
+
+    /// Program n bytes to the OTP, the bits can never be erased once programmed.
+    /// Note that the OTP can't be programmed once locked down with [`MX25R::write_security_register`]
+    pub fn program(&mut self, addr: u32, bytes: &[u8]) -> Result<(), Error<E>> {
+        Protocol::check_otp(addr, bytes.len())?;
+        for (addr, chunk) in page_chunks(addr, bytes) {
+            self.flash.write_page(addr, chunk)?.wait(self.flash)?;
+        }
+        Ok(())
+    }
+
+    /// Exit the secured OTP, waiting for any ongoing program to complete
+    pub fn exit(mut self) -> Result<(), Error<E>> {
+        self.active = false;
+        self.flash.wait_wip()?;
+        self.flash.exit_secure_opt()
+    }
+}
+
+impl<const SIZE: u32, SPI, D> Drop for SecureOtp<'_, SIZE, SPI, D>
+where
+    SPI: FlashBus,
+    D: DelayNs,
+{
+    fn drop(&mut self) {
+        if self.active {
+            self.flash.protocol.otp_guard_dropped();
+            // Errors can't be reported here, the exit is retried on the next instruction if it fails
+            let _ = self.flash.flush_secure_otp_exit();
+        }
+    }
+}
+
+/// A chip in deep power down, created with [`MX25R::deep_power_down`]
+pub struct MX25RSleeping<const SIZE: u32, SPI, D>
+where
+    SPI: FlashBus,
+    D: DelayNs,
+{
+    flash: MX25R<SIZE, SPI, D>,
+}
+
+impl<const SIZE: u32, SPI, D, E> MX25RSleeping<SIZE, SPI, D>
+where
+    SPI: FlashBus<Error = E>,
+    D: DelayNs,
+{
+    /// Release the chip from deep power down and wait for its recovery time with the delay of the driver, before giving
+    /// the driver back. On failure, the sleeping chip is returned with the error
+    pub fn wake(mut self) -> Result<MX25R<SIZE, SPI, D>, (Self, Error<E>)> {
+        // The electronic id instruction without its dummy and data phases is the release from deep power down
+        if let Err(e) = self.flash.command_write(Command::ReadElectronicId, &[]) {
+            return Err((self, e));
+        }
+        self.flash.delay.delay_us(DEEP_POWER_DOWN_RECOVERY_US);
+        Ok(self.flash)
     }
 }
 
 /// Implementation of the [`NorFlash`](embedded_storage::nor_flash) trait of the  crate
 mod es {
 
+    use crate::bus::FlashBus;
+    use crate::erase::ErasePlan;
     use crate::error::Error;
+    use crate::protocol::{page_chunks, PowerBoost, SetPowerMode};
+    use crate::SECTOR_SIZE;
     use crate::{check_erase, check_write};
-    use crate::{BLOCK32_SIZE, BLOCK64_SIZE, PAGE_SIZE, SECTOR_SIZE};
-    use embedded_hal::spi::SpiDevice;
     use embedded_storage::nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash};
 
     use super::MX25R;
+    use embedded_hal::delay::DelayNs;
 
-    impl<const SIZE: u32, SPI: SpiDevice> embedded_storage::nor_flash::ErrorType for MX25R<SIZE, SPI> {
+    impl<const SIZE: u32, SPI: FlashBus, D: DelayNs> embedded_storage::nor_flash::ErrorType
+        for MX25R<SIZE, SPI, D>
+    {
         type Error = Error<SPI::Error>;
     }
 
-    impl<const SIZE: u32, SPI: SpiDevice> ReadNorFlash for MX25R<SIZE, SPI> {
+    impl<const SIZE: u32, SPI: FlashBus, D: DelayNs> ReadNorFlash for MX25R<SIZE, SPI, D> {
         const READ_SIZE: usize = 1;
 
         fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
-            self.read_fast(offset, bytes)
+            check_write(self.capacity(), offset, bytes.len())?;
+            if bytes.is_empty() {
+                // Nothing to send, the offset may be the end of the flash
+                return Ok(());
+            }
+            let boosted = self.run(PowerBoost::read(bytes.len()), &mut [])?;
+            let res = self.read_with_mode(offset, bytes);
+            let restored = self.run(SetPowerMode::restore(boosted), &mut []);
+            res.and(restored)
         }
 
         fn capacity(&self) -> usize {
-            Self::CAPACITY
+            MX25R::capacity(self)
         }
     }
 
-    impl<const SIZE: u32, SPI: SpiDevice> NorFlash for MX25R<SIZE, SPI> {
+    impl<const SIZE: u32, SPI: FlashBus, D: DelayNs> NorFlash for MX25R<SIZE, SPI, D> {
         const WRITE_SIZE: usize = 1;
         const ERASE_SIZE: usize = SECTOR_SIZE as usize;
 
-        fn erase(&mut self, mut from: u32, to: u32) -> Result<(), Self::Error> {
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
             check_erase(self.capacity(), from, to)?;
 
-            while from < to {
-                self.wait_wip()?;
-                let addr_diff = to - from;
-                if addr_diff % BLOCK64_SIZE == 0 {
-                    self.erase_block64(from)?;
-                    from += BLOCK64_SIZE;
-                } else if addr_diff % BLOCK32_SIZE == 0 {
-                    self.erase_block32(from)?;
-                    from += BLOCK32_SIZE;
-                } else if addr_diff % SECTOR_SIZE == 0 {
-                    self.erase_sector(from)?;
-                    from += SECTOR_SIZE;
-                } else {
-                    return Err(Error::NotAligned);
-                }
+            let plan = ErasePlan::new(from, to).ok_or(Error::NotAligned)?;
+            for step in plan {
+                self.erase_step(step)?.wait(self)?;
             }
             Ok(())
         }
 
-        fn write(&mut self, mut offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
             check_write(self.capacity(), offset, bytes.len())?;
+            let boosted = self.run(PowerBoost::write(bytes.len()), &mut [])?;
+            let res = self.write_pages(offset, bytes);
+            let restored = self.run(SetPowerMode::restore(boosted), &mut []);
+            res.and(restored)
+        }
+    }
 
-            // Write first chunk, taking into account that given addres might
-            // point to a location that is not on a page boundary,
-            let chunk_len = (PAGE_SIZE - (offset & 0x000000FF)) as usize;
-            let mut chunk_len = chunk_len.min(bytes.len());
-            self.write_page(offset, &bytes[..chunk_len])?;
-
-            loop {
-                bytes = &bytes[chunk_len..];
-                offset += chunk_len as u32;
-                chunk_len = bytes.len().min(PAGE_SIZE as usize);
-                if chunk_len == 0 {
-                    break;
-                }
-                self.write_page(offset, &bytes[..chunk_len])?;
-            }
+    impl<const SIZE: u32, SPI: FlashBus, D: DelayNs> MultiwriteNorFlash for MX25R<SIZE, SPI, D> {}
 
+    impl<const SIZE: u32, SPI: FlashBus, D: DelayNs> MX25R<SIZE, SPI, D> {
+        /// Program `bytes` page by page from `offset`, the range is already checked
+        fn write_pages(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error<SPI::Error>> {
+            for (addr, chunk) in page_chunks(offset, bytes) {
+                self.write_page(addr, chunk)?.wait(self)?;
+            }
             Ok(())
         }
     }
-
-    impl<const SIZE: u32, SPI: SpiDevice> MultiwriteNorFlash for MX25R<SIZE, SPI> {}
 }
@@ -0,0 +1,227 @@
+//! Bus abstraction used by the drivers to support single, dual and quad transfers.
+//!
+//! A flash instruction is split in four phases: command, address, dummy cycles and data, each with its own number of lines.
+//! Any [`SpiDevice`](embedded_hal::spi::SpiDevice) implements [`FlashBus`] (and any async one implements [`AsyncFlashBus`])
+//! and supports single line transfers only. Implement the traits for your QSPI peripheral to use the dual and quad instructions.
+
+use core::fmt::Debug;
+
+use embedded_hal::spi::Operation;
+
+use crate::command::Command;
+
+/// Number of data lines used by a phase of a transfer
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BusWidth {
+    Single,
+    Dual,
+    Quad,
+}
+
+impl BusWidth {
+    /// Number of lines used
+    pub const fn lines(&self) -> u8 {
+        match self {
+            BusWidth::Single => 1,
+            BusWidth::Dual => 2,
+            BusWidth::Quad => 4,
+        }
+    }
+}
+
+/// Read instruction used by the `ReadNorFlash` implementation of the drivers
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMode {
+    /// Normal read (READ)
+    Normal,
+    /// Fast read (FAST_READ)
+    #[default]
+    Fast,
+    /// Dual output read (DREAD), data on 2 lines
+    DualOutput,
+    /// Dual I/O read (2READ), address and data on 2 lines
+    DualIo,
+    /// Quad output read (QREAD), data on 4 lines
+    QuadOutput,
+    /// Quad I/O read (4READ), address and data on 4 lines
+    QuadIo,
+}
+
+impl ReadMode {
+    /// Number of lines required by the read instruction
+    pub const fn width(&self) -> BusWidth {
+        match self {
+            ReadMode::Normal | ReadMode::Fast => BusWidth::Single,
+            ReadMode::DualOutput | ReadMode::DualIo => BusWidth::Dual,
+            ReadMode::QuadOutput | ReadMode::QuadIo => BusWidth::Quad,
+        }
+    }
+}
+
+/// A flash instruction, split in its phases.
+/// The address is always 3 bytes long, the dummy phase is expressed in clock cycles.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub opcode_width: BusWidth,
+    pub address: Option<u32>,
+    pub address_width: BusWidth,
+    pub dummy_cycles: u8,
+    pub data_width: BusWidth,
+}
+
+impl Instruction {
+    /// A single line instruction without address nor dummy cycles
+    pub const fn new(opcode: u8) -> Self {
+        Instruction {
+            opcode,
+            opcode_width: BusWidth::Single,
+            address: None,
+            address_width: BusWidth::Single,
+            dummy_cycles: 0,
+            data_width: BusWidth::Single,
+        }
+    }
+
+    /// Add an address phase
+    pub const fn with_address(mut self, address: u32, width: BusWidth) -> Self {
+        self.address = Some(address);
+        self.address_width = width;
+        self
+    }
+
+    /// Set the width of the address phase, the address itself is set by the driver
+    pub const fn with_address_width(mut self, width: BusWidth) -> Self {
+        self.address_width = width;
+        self
+    }
+
+    /// Add a dummy phase of `cycles` clock cycles
+    pub const fn with_dummy_cycles(mut self, cycles: u8) -> Self {
+        self.dummy_cycles = cycles;
+        self
+    }
+
+    /// Set the width of the data phase
+    pub const fn with_data_width(mut self, width: BusWidth) -> Self {
+        self.data_width = width;
+        self
+    }
+
+    /// The widest phase of the instruction
+    pub fn max_width(&self) -> BusWidth {
+        self.opcode_width
+            .max(self.address_width)
+            .max(self.data_width)
+    }
+}
+
+/// A bus able to send flash instructions
+pub trait FlashBus {
+    type Error: Debug;
+
+    /// If the bus can use `width` lines
+    fn supports(&self, width: BusWidth) -> bool;
+
+    /// Send the instruction and read the data phase into `data`
+    fn read(&mut self, instruction: &Instruction, data: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Send the instruction followed by `data`
+    fn write(&mut self, instruction: &Instruction, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// An async bus able to send flash instructions
+#[allow(async_fn_in_trait)]
+pub trait AsyncFlashBus {
+    type Error: Debug;
+
+    /// If the bus can use `width` lines
+    fn supports(&self, width: BusWidth) -> bool;
+
+    /// Send the instruction and read the data phase into `data`
+    async fn read(&mut self, instruction: &Instruction, data: &mut [u8])
+        -> Result<(), Self::Error>;
+
+    /// Send the instruction followed by `data`
+    async fn write(&mut self, instruction: &Instruction, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Maximum length of the command, address and dummy phases on a single line bus
+const HEADER_MAX_LEN: usize = 8;
+
+/// Encode the command, address and dummy phases for a single line bus, returns the used length
+fn encode_header(instruction: &Instruction, header: &mut [u8; HEADER_MAX_LEN]) -> usize {
+    debug_assert!(instruction.max_width() == BusWidth::Single);
+    debug_assert!(instruction.dummy_cycles.is_multiple_of(8));
+
+    header[0] = instruction.opcode;
+    let mut len = 1;
+    if let Some(addr) = instruction.address {
+        header[1..4].copy_from_slice(&addr.to_be_bytes()[1..]);
+        len += 3;
+    }
+    let dummy = (instruction.dummy_cycles / 8) as usize;
+    header[len..len + dummy].fill(Command::Dummy as u8);
+    len + dummy
+}
+
+impl<T> FlashBus for T
+where
+    T: embedded_hal::spi::SpiDevice,
+{
+    type Error = T::Error;
+
+    fn supports(&self, width: BusWidth) -> bool {
+        width == BusWidth::Single
+    }
+
+    fn read(&mut self, instruction: &Instruction, data: &mut [u8]) -> Result<(), Self::Error> {
+        let mut header = [0; HEADER_MAX_LEN];
+        let len = encode_header(instruction, &mut header);
+        self.transaction(&mut [Operation::Write(&header[..len]), Operation::Read(data)])
+    }
+
+    fn write(&mut self, instruction: &Instruction, data: &[u8]) -> Result<(), Self::Error> {
+        let mut header = [0; HEADER_MAX_LEN];
+        let len = encode_header(instruction, &mut header);
+        if data.is_empty() {
+            return embedded_hal::spi::SpiDevice::write(self, &header[..len]);
+        }
+        self.transaction(&mut [Operation::Write(&header[..len]), Operation::Write(data)])
+    }
+}
+
+impl<T> AsyncFlashBus for T
+where
+    T: embedded_hal_async::spi::SpiDevice,
+{
+    type Error = T::Error;
+
+    fn supports(&self, width: BusWidth) -> bool {
+        width == BusWidth::Single
+    }
+
+    async fn read(
+        &mut self,
+        instruction: &Instruction,
+        data: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let mut header = [0; HEADER_MAX_LEN];
+        let len = encode_header(instruction, &mut header);
+        self.transaction(&mut [Operation::Write(&header[..len]), Operation::Read(data)])
+            .await
+    }
+
+    async fn write(&mut self, instruction: &Instruction, data: &[u8]) -> Result<(), Self::Error> {
+        let mut header = [0; HEADER_MAX_LEN];
+        let len = encode_header(instruction, &mut header);
+        if data.is_empty() {
+            return embedded_hal_async::spi::SpiDevice::write(self, &header[..len]).await;
+        }
+        self.transaction(&mut [Operation::Write(&header[..len]), Operation::Write(data)])
+            .await
+    }
+}
@@ -1,15 +1,17 @@
+use crate::bus::Instruction;
+
 /// Possible commands to send to the MX25R
 #[repr(u8)]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Command {
     Read = 0x03,
     ReadF = 0x0B,
-    //Read2 = 0xBB,
-    //ReadD = 0x3B,
-    //Read4 = 0xEB,
-    //ReadQ = 0x6B,
+    Read2 = 0xBB,
+    ReadD = 0x3B,
+    Read4 = 0xEB,
+    ReadQ = 0x6B,
     ProgramPage = 0x02,
-    //ProgramPage4 = 0x38,
+    ProgramPage4 = 0x38,
     SectorErase = 0x20,
     BlockErase32 = 0x52,
     BlockErase = 0xD8,
@@ -36,3 +38,109 @@ pub enum Command {
     ResetMemory = 0x99,
     Dummy = 0xFF,
 }
+
+impl Command {
+    /// A single line instruction for the command, without address nor dummy cycles
+    pub const fn instruction(self) -> Instruction {
+        Instruction::new(self as u8)
+    }
+
+    /// The command of an opcode, `None` for the opcodes the driver doesn't send
+    pub const fn from_opcode(opcode: u8) -> Option<Self> {
+        let command = match opcode {
+            0x03 => Command::Read,
+            0x0B => Command::ReadF,
+            0xBB => Command::Read2,
+            0x3B => Command::ReadD,
+            0xEB => Command::Read4,
+            0x6B => Command::ReadQ,
+            0x02 => Command::ProgramPage,
+            0x38 => Command::ProgramPage4,
+            0x20 => Command::SectorErase,
+            0x52 => Command::BlockErase32,
+            0xD8 => Command::BlockErase,
+            0x60 => Command::ChipErase,
+            0x5A => Command::ReadSfdp,
+            0x06 => Command::WriteEnable,
+            0x04 => Command::WriteDisable,
+            0x05 => Command::ReadStatus,
+            0x15 => Command::ReadConfig,
+            0x01 => Command::WriteStatus,
+            0xB0 => Command::ProgramEraseSuspend,
+            0x30 => Command::ProgramEraseResume,
+            0xB9 => Command::DeepPowerDown,
+            0xC0 => Command::SetBurstLength,
+            0x9F => Command::ReadIdentification,
+            0x90 => Command::ReadManufacturerId,
+            0xAB => Command::ReadElectronicId,
+            0xB1 => Command::EnterSecureOTP,
+            0xC1 => Command::ExitSecureOTP,
+            0x2B => Command::ReadSecurityRegister,
+            0x2F => Command::WriteSecurityRegister,
+            0x00 => Command::Nop,
+            0x66 => Command::ResetEnable,
+            0x99 => Command::ResetMemory,
+            0xFF => Command::Dummy,
+            _ => return None,
+        };
+        Some(command)
+    }
+
+    /// Mnemonic of the command in the datasheet
+    pub const fn name(self) -> &'static str {
+        match self {
+            Command::Read => "READ",
+            Command::ReadF => "FAST_READ",
+            Command::Read2 => "2READ",
+            Command::ReadD => "DREAD",
+            Command::Read4 => "4READ",
+            Command::ReadQ => "QREAD",
+            Command::ProgramPage => "PP",
+            Command::ProgramPage4 => "4PP",
+            Command::SectorErase => "SE",
+            Command::BlockErase32 => "BE32K",
+            Command::BlockErase => "BE",
+            Command::ChipErase => "CE",
+            Command::ReadSfdp => "RDSFDP",
+            Command::WriteEnable => "WREN",
+            Command::WriteDisable => "WRDI",
+            Command::ReadStatus => "RDSR",
+            Command::ReadConfig => "RDCR",
+            Command::WriteStatus => "WRSR",
+            Command::ProgramEraseSuspend => "PGM/ERS Suspend",
+            Command::ProgramEraseResume => "PGM/ERS Resume",
+            Command::DeepPowerDown => "DP",
+            Command::SetBurstLength => "SBL",
+            Command::ReadIdentification => "RDID",
+            Command::ReadManufacturerId => "REMS",
+            Command::ReadElectronicId => "RES",
+            Command::EnterSecureOTP => "ENSO",
+            Command::ExitSecureOTP => "EXSO",
+            Command::ReadSecurityRegister => "RDSCUR",
+            Command::WriteSecurityRegister => "WRSCUR",
+            Command::Nop => "NOP",
+            Command::ResetEnable => "RSTEN",
+            Command::ResetMemory => "RST",
+            Command::Dummy => "DUMMY",
+        }
+    }
+
+    /// If a 3 bytes address follows the opcode, and the number of dummy bytes after it, as sent on a single line bus.
+    /// The dual and quad I/O reads send their address on several lines and never go through a single line bus
+    pub const fn header(self) -> (bool, u8) {
+        match self {
+            Command::Read
+            | Command::Read2
+            | Command::Read4
+            | Command::ProgramPage
+            | Command::ProgramPage4
+            | Command::SectorErase
+            | Command::BlockErase32
+            | Command::BlockErase
+            | Command::ReadManufacturerId => (true, 0),
+            Command::ReadF | Command::ReadD | Command::ReadQ | Command::ReadSfdp => (true, 1),
+            Command::ReadElectronicId => (false, 3),
+            _ => (false, 0),
+        }
+    }
+}
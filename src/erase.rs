@@ -0,0 +1,92 @@
+//! Planning of the erase instructions covering an address range.
+//!
+//! A sector aligned range is split in the fewest aligned 4kB sector, 32kB and 64kB block erases,
+//! the plan can be previewed with its estimated duration before being run with the `erase_step` function of the drivers.
+
+use crate::{
+    timing::{Operation, Timings},
+    BLOCK32_SIZE, BLOCK64_SIZE, SECTOR_SIZE,
+};
+
+/// An erase instruction of an [`ErasePlan`], with the address of the erased area
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EraseStep {
+    Sector(u32),
+    Block32(u32),
+    Block64(u32),
+}
+
+impl EraseStep {
+    /// Address of the erased area
+    pub const fn addr(&self) -> u32 {
+        match self {
+            EraseStep::Sector(addr) | EraseStep::Block32(addr) | EraseStep::Block64(addr) => *addr,
+        }
+    }
+
+    /// Size of the erased area in bytes
+    pub const fn size(&self) -> u32 {
+        match self {
+            EraseStep::Sector(_) => SECTOR_SIZE,
+            EraseStep::Block32(_) => BLOCK32_SIZE,
+            EraseStep::Block64(_) => BLOCK64_SIZE,
+        }
+    }
+
+    /// The operation run by the chip
+    pub const fn operation(&self) -> Operation {
+        match self {
+            EraseStep::Sector(_) => Operation::SectorErase,
+            EraseStep::Block32(_) => Operation::Block32Erase,
+            EraseStep::Block64(_) => Operation::Block64Erase,
+        }
+    }
+}
+
+/// The fewest aligned erases covering a range, in increasing address order
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErasePlan {
+    from: u32,
+    to: u32,
+}
+
+impl ErasePlan {
+    /// Plan the erase of `from..to`, returns `None` if the bounds are not sector aligned or reversed.
+    /// The bounds are not checked against the capacity of the chip
+    pub fn new(from: u32, to: u32) -> Option<Self> {
+        if from > to || !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) {
+            return None;
+        }
+        Some(ErasePlan { from, to })
+    }
+
+    /// Estimated duration of the remaining erases in microseconds, use the typical or maximum durations of the part
+    pub fn duration_us(&self, timings: &Timings) -> u64 {
+        self.clone()
+            .map(|step| timings.duration_us(step.operation()) as u64)
+            .sum()
+    }
+}
+
+impl Iterator for ErasePlan {
+    type Item = EraseStep;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.to - self.from;
+        if remaining == 0 {
+            return None;
+        }
+        // Picking the largest aligned block fitting from the start address gives the fewest erases
+        let step = if self.from.is_multiple_of(BLOCK64_SIZE) && remaining >= BLOCK64_SIZE {
+            EraseStep::Block64(self.from)
+        } else if self.from.is_multiple_of(BLOCK32_SIZE) && remaining >= BLOCK32_SIZE {
+            EraseStep::Block32(self.from)
+        } else {
+            EraseStep::Sector(self.from)
+        };
+        self.from += step.size();
+        Some(step)
+    }
+}
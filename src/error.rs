@@ -1,4 +1,4 @@
-use core::fmt::Debug;
+use core::fmt::{self, Debug};
 
 use embedded_storage_async::nor_flash::{NorFlashError, NorFlashErrorKind};
 
@@ -20,6 +20,42 @@ pub enum Error<SpiError> {
 
     /// The device is busy
     Busy,
+
+    /// The SFDP tables are missing or malformed
+    Sfdp,
+
+    /// The chip identification doesn't match a supported part
+    UnknownDevice,
+
+    /// The bus doesn't support the number of lines required by the instruction
+    Unsupported,
+
+    /// The program operation at the given address failed, as reported by the security register
+    ProgramFailed { addr: u32 },
+
+    /// The erase operation at the given address failed, as reported by the security register
+    EraseFailed { addr: u32 },
+
+    /// The chip was still busy after the maximum duration of the operation
+    Timeout,
+}
+
+impl<SpiError: Debug> fmt::Display for Error<SpiError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spi(e) => write!(f, "SPI error: {:?}", e),
+            Error::Value => f.write_str("invalid value"),
+            Error::OutOfBounds => f.write_str("address out of bounds"),
+            Error::NotAligned => f.write_str("address not aligned"),
+            Error::Busy => f.write_str("the device is busy"),
+            Error::Sfdp => f.write_str("missing or malformed SFDP tables"),
+            Error::UnknownDevice => f.write_str("unsupported device"),
+            Error::Unsupported => f.write_str("instruction not supported by the bus"),
+            Error::ProgramFailed { addr } => write!(f, "program failed at {:#08x}", addr),
+            Error::EraseFailed { addr } => write!(f, "erase failed at {:#08x}", addr),
+            Error::Timeout => f.write_str("the operation timed out"),
+        }
+    }
 }
 
 impl<SpiError: Debug> NorFlashError for Error<SpiError> {
@@ -30,6 +66,12 @@ impl<SpiError: Debug> NorFlashError for Error<SpiError> {
             Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
             Error::NotAligned => NorFlashErrorKind::NotAligned,
             Error::Busy => NorFlashErrorKind::Other,
+            Error::Sfdp => NorFlashErrorKind::Other,
+            Error::UnknownDevice => NorFlashErrorKind::Other,
+            Error::Unsupported => NorFlashErrorKind::Other,
+            Error::ProgramFailed { .. } => NorFlashErrorKind::Other,
+            Error::EraseFailed { .. } => NorFlashErrorKind::Other,
+            Error::Timeout => NorFlashErrorKind::Other,
         }
     }
 }
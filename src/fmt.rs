@@ -0,0 +1,14 @@
+//! Logging through `defmt` or `log`, depending on the enabled features.
+//! The format strings must be understood by both, `{}` and `{:?}` are.
+
+/// Log at the trace level, a no-op without the `defmt` and `log` features
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        defmt::trace!($($arg)*);
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+    }};
+}
+
+pub(crate) use trace;
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 //! This is a platform agnostic library for the Macronix MX25R NOR flash series using [embedded-hal](https://github.com/rust-embedded/embedded-hal).
 //!
 //! Multiple chips are supported:
@@ -10,12 +10,27 @@
 //! * [MX25R1635F](https://www.macronix.com/Lists/Datasheet/Attachments/7595/MX25R1635F,%20Wide%20Range,%2016Mb,%20v1.6.pdf)
 //! * [MX25R3235F](https://www.macronix.com/Lists/Datasheet/Attachments/7966/MX25R3235F,%20Wide%20Range,%2032Mb,%20v1.8.pdf)
 //! * [MX25R6435F](https://www.macronix.com/Lists/Datasheet/Attachments/7913/MX25R6435F,%20Wide%20Range,%2064Mb,%20v1.5.pdf)
+//!
+//! With the `std` feature, the [`sim`] module provides a simulated chip to run the drivers on the host.
 
 pub mod asynchronous;
 pub mod blocking;
+pub mod bus;
 mod command;
+pub mod erase;
 pub mod error;
+mod fmt;
+pub mod part;
+pub mod poll;
+pub mod power;
+pub mod protection;
+mod protocol;
 pub mod register;
+pub mod sfdp;
+#[cfg(feature = "std")]
+pub mod sim;
+pub mod timing;
+pub mod trace;
 
 use crate::error::Error;
 
@@ -25,12 +40,22 @@ pub const BLOCK32_SIZE: u32 = BLOCK64_SIZE / 2;
 pub const SECTOR_SIZE: u32 = 0x1000;
 pub const PAGE_SIZE: u32 = 0x100;
 
+/// Size of the secured OTP (8Kbit)
+pub const SECURE_OTP_SIZE: u32 = 0x400;
+
+/// Time needed by the chip to recover from the deep power down once released (tRDP), in microseconds
+pub const DEEP_POWER_DOWN_RECOVERY_US: u32 = 35;
+
+/// Value of the `SIZE` generic of the drivers when the capacity is only known at runtime.
+/// Their capacity is given by the `capacity` method, the deprecated `CAPACITY` constant is meaningless for them.
+pub const DYNAMIC_SIZE: u32 = 0;
+
 pub(crate) fn check_erase<E>(capacity: usize, from: u32, to: u32) -> Result<(), Error<E>> {
     let capacity = capacity as u32;
     if from > to || to > capacity {
         return Err(Error::OutOfBounds);
     }
-    if from % SECTOR_SIZE != 0 || to % SECTOR_SIZE != 0 {
+    if !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) {
         return Err(Error::NotAligned);
     }
     Ok(())
@@ -0,0 +1,74 @@
+use crate::register::{ManufacturerId, MemoryDensity, MemoryType};
+
+/// Manufacturer ID reported by Macronix chips
+pub const MACRONIX_ID: ManufacturerId = ManufacturerId(0xC2);
+
+/// Memory type reported by the MX25R series
+pub const MX25R_MEMORY_TYPE: MemoryType = MemoryType(0x28);
+
+/// The supported chips of the MX25R series
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Part {
+    MX25R512F,
+    MX25R1035F,
+    MX25R2035F,
+    MX25R4035F,
+    MX25R8035F,
+    MX25R1635F,
+    MX25R3235F,
+    MX25R6435F,
+}
+
+impl Part {
+    /// All the supported parts, ordered by capacity
+    pub const ALL: [Part; 8] = [
+        Part::MX25R512F,
+        Part::MX25R1035F,
+        Part::MX25R2035F,
+        Part::MX25R4035F,
+        Part::MX25R8035F,
+        Part::MX25R1635F,
+        Part::MX25R3235F,
+        Part::MX25R6435F,
+    ];
+
+    /// Memory density reported by [`read_identification`](crate::blocking::MX25R::read_identification)
+    pub const fn density(&self) -> MemoryDensity {
+        MemoryDensity(0x10 + *self as u8)
+    }
+
+    /// Find the part matching the memory density reported by the chip
+    pub fn from_density(density: MemoryDensity) -> Option<Self> {
+        Self::ALL.into_iter().find(|part| part.density() == density)
+    }
+
+    /// Find the part matching a capacity in bytes
+    pub fn from_capacity(capacity: usize) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|part| part.capacity() == capacity)
+    }
+
+    /// Find the part matching the full identification of the chip, returns `None` for non MX25R chips
+    pub fn from_identification(
+        manufacturer: ManufacturerId,
+        memory_type: MemoryType,
+        density: MemoryDensity,
+    ) -> Option<Self> {
+        if manufacturer != MACRONIX_ID || memory_type != MX25R_MEMORY_TYPE {
+            return None;
+        }
+        Self::from_density(density)
+    }
+
+    /// Capacity of the chip in bytes
+    pub const fn capacity(&self) -> usize {
+        0x10000 << (*self as usize)
+    }
+
+    /// Last valid address of the chip, the equivalent of the `SIZE` generic of the drivers
+    pub const fn last_address(&self) -> u32 {
+        self.capacity() as u32 - 1
+    }
+}
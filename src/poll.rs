@@ -0,0 +1,66 @@
+//! Strategies deciding how long the async driver sleeps between the polls of the write in progress bit.
+//!
+//! Polling the status register keeps the bus busy and the MCU awake, while sleeping too long delays the next operation.
+//! The delays are run by the `DelayNs` of the driver, a delay of 0 yields to the executor instead.
+//! Each poll counts for at least 1µs towards the maximum duration of the operation, so every strategy times out.
+
+/// Decides the delay before each poll of the write in progress bit
+pub trait PollStrategy {
+    /// Delay before the next poll in microseconds.
+    /// `typical_us` is the typical duration of the operation waited for, 0 if unknown, and `polls` the number of polls
+    /// that found the chip busy so far, starting at 1
+    fn delay_us(&mut self, typical_us: u32, polls: u32) -> u32;
+}
+
+/// Sleep the typical duration of the operation, then back off exponentially until the chip is ready.
+/// This is the default strategy, it keeps the number of polls low for both the short programs and the long erases
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// First delay after the typical duration
+    pub initial_us: u32,
+    /// Longest delay between two polls
+    pub max_us: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_us: 10,
+            max_us: 100_000,
+        }
+    }
+}
+
+impl PollStrategy for Backoff {
+    fn delay_us(&mut self, typical_us: u32, polls: u32) -> u32 {
+        if polls == 1 && typical_us > 0 {
+            return typical_us;
+        }
+        let backoffs = if typical_us > 0 { polls - 2 } else { polls - 1 };
+        let delay_us = (self.initial_us as u64) << backoffs.min(32);
+        delay_us.min(self.max_us as u64) as u32
+    }
+}
+
+/// Poll at a fixed interval in microseconds
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedInterval(pub u32);
+
+impl PollStrategy for FixedInterval {
+    fn delay_us(&mut self, _typical_us: u32, _polls: u32) -> u32 {
+        self.0
+    }
+}
+
+/// Yield to the executor between the polls, the previous behavior of the driver
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Yield;
+
+impl PollStrategy for Yield {
+    fn delay_us(&mut self, _typical_us: u32, _polls: u32) -> u32 {
+        0
+    }
+}
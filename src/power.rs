@@ -0,0 +1,23 @@
+//! Managed power mode of the drivers.
+//!
+//! The chip starts in the ultra low power mode, which caps the SPI clock and slows down the programs and erases.
+//! When managed, the drivers switch to the high performance mode for the transfers reaching a threshold and back to
+//! the ultra low power mode once done. Each switch is a write of the configuration register, so small transfers aren't worth it.
+
+/// Transfer lengths in bytes from which the [`NorFlash`](embedded_storage::nor_flash::NorFlash) reads and writes
+/// switch the chip to the high performance mode
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerThresholds {
+    pub read: usize,
+    pub write: usize,
+}
+
+impl Default for PowerThresholds {
+    fn default() -> Self {
+        PowerThresholds {
+            read: 64 * 1024,
+            write: 16 * 1024,
+        }
+    }
+}
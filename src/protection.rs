@@ -0,0 +1,73 @@
+//! Mapping between the block protect bits and the protected address ranges.
+//!
+//! Setting `n` of the block protect bits protects `2^(n-1)` units from the top or bottom of the array,
+//! where a unit is a 64kB block, or 1/64 of the array for the MX25R6435F. Settings beyond the capacity protect the whole chip.
+
+use core::ops::Range;
+
+use crate::{register::ProtectedArea, BLOCK64_SIZE};
+
+/// Largest value of the block protect bits
+pub const MAX_PROTECTED_BLOCK: u8 = 0x0F;
+
+/// The block protection setting, made of the block protect bits of the status register and
+/// the top/bottom bit of the configuration register
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockProtection {
+    pub protected_block: u8,
+    pub area: ProtectedArea,
+}
+
+impl BlockProtection {
+    /// Number of bytes protected on a chip of `capacity` bytes
+    pub fn protected_size(&self, capacity: usize) -> u32 {
+        if self.protected_block == 0 {
+            return 0;
+        }
+        let capacity = capacity as u64;
+        let unit = (BLOCK64_SIZE as u64).max(capacity / 64);
+        let shift = (self.protected_block.min(MAX_PROTECTED_BLOCK) - 1) as u32;
+        (unit << shift).min(capacity) as u32
+    }
+
+    /// Protected address range on a chip of `capacity` bytes, empty if nothing is protected
+    pub fn range(&self, capacity: usize) -> Range<u32> {
+        let size = self.protected_size(capacity);
+        if size == 0 {
+            return 0..0;
+        }
+        match self.area {
+            ProtectedArea::Top => capacity as u32 - size..capacity as u32,
+            ProtectedArea::Bottom => 0..size,
+        }
+    }
+
+    /// Find the smallest setting of `area` protecting at least `range` on a chip of `capacity` bytes.
+    ///
+    /// An empty range gives the setting protecting nothing. Returns `None` if the range is beyond the capacity, or if
+    /// only the whole chip covers it while it doesn't reach the end selected by `area`: such a range is protected from
+    /// the other end, or with a range of the whole chip.
+    pub fn for_range(capacity: usize, range: Range<u32>, area: ProtectedArea) -> Option<Self> {
+        if range.is_empty() {
+            return Some(BlockProtection {
+                protected_block: 0,
+                area,
+            });
+        }
+        let reaches_end = match area {
+            ProtectedArea::Top => range.end == capacity as u32,
+            ProtectedArea::Bottom => range.start == 0,
+        };
+        (1..=MAX_PROTECTED_BLOCK)
+            .map(|protected_block| BlockProtection {
+                protected_block,
+                area,
+            })
+            .find(|protection| {
+                let protected = protection.range(capacity);
+                protected.start <= range.start && range.end <= protected.end
+            })
+            .filter(|protection| reaches_end || protection.range(capacity) != (0..capacity as u32))
+    }
+}
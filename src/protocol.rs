@@ -0,0 +1,525 @@
+//! Sans-IO core shared by the blocking and async drivers.
+//!
+//! The core validates the arguments, encodes the instructions, decodes the responses and tracks what is known about the chip.
+//! The drivers only move the bytes over their bus and wait when told to, so a command or a fix behaves the same in both.
+//! The operations made of several exchanges, e.g. a read while erasing, are state machines run by the drivers step by step.
+
+use core::ops::Range;
+
+use crate::{
+    bus::{BusWidth, Instruction, ReadMode},
+    command::Command,
+    erase::EraseStep,
+    error::Error,
+    part::Part,
+    poll::PollStrategy,
+    power::PowerThresholds,
+    protection::BlockProtection,
+    register::*,
+    sfdp::{ParameterHeader, BASIC_FLASH_PARAMETER_MAX_DWORDS},
+    timing::{Operation, Timings},
+    BLOCK32_SIZE, BLOCK64_SIZE, PAGE_SIZE, SECTOR_SIZE, SECURE_OTP_SIZE,
+};
+
+mod sequence;
+
+pub(crate) use sequence::*;
+
+/// Number of polls of the wip bit spread over the maximum duration of an operation by the blocking driver
+const WAIT_POLLS: u32 = 1000;
+
+/// Longest interval between the polls of the blocking driver, the longest delay of the default [`Backoff`](crate::poll::Backoff)
+const MAX_WAIT_INTERVAL_US: u32 = 100_000;
+
+/// The state of the chip as known by a driver
+pub(crate) struct Protocol {
+    /// Last valid address
+    pub(crate) size: u32,
+    /// The quad enable bit is known to be set
+    pub(crate) quad_enabled: bool,
+    /// Cached dummy cycle bit of the configuration register
+    pub(crate) dummy_cycle: Option<bool>,
+    pub(crate) read_mode: ReadMode,
+    pub(crate) burst_length: BurstLength,
+    /// Last known power mode, from the configuration register
+    pub(crate) power_mode: Option<PowerMode>,
+    /// Thresholds of the managed power mode, if enabled
+    pub(crate) power_thresholds: Option<PowerThresholds>,
+    /// Check the security register once a program or erase completes
+    pub(crate) failure_detection: bool,
+    /// A secure OTP guard was dropped without exiting, the exit is sent before the next instruction
+    otp_exit_pending: bool,
+    /// The operation keeping the chip busy, if any
+    pub(crate) pending: Option<Operation>,
+    /// Address of the pending operation
+    pub(crate) pending_addr: u32,
+    /// The pending operation while it's suspended, the chip reports ready until it's resumed
+    pub(crate) suspended: Option<Operation>,
+}
+
+impl Protocol {
+    pub(crate) const fn new(size: u32) -> Self {
+        Protocol {
+            size,
+            quad_enabled: false,
+            dummy_cycle: None,
+            read_mode: ReadMode::Fast,
+            burst_length: BurstLength::Disabled,
+            power_mode: None,
+            power_thresholds: None,
+            failure_detection: false,
+            otp_exit_pending: false,
+            pending: None,
+            pending_addr: 0,
+            suspended: None,
+        }
+    }
+
+    /// Capacity of the chip in bytes
+    pub(crate) fn capacity(&self) -> usize {
+        self.size as usize + 1
+    }
+
+    pub(crate) fn part(&self) -> Option<Part> {
+        Part::from_capacity(self.capacity())
+    }
+
+    /// The limits of the largest part are used if the capacity doesn't match a part
+    pub(crate) fn timings(&self) -> Timings {
+        self.part().unwrap_or(Part::MX25R6435F).max_timings()
+    }
+
+    pub(crate) fn typical_timings(&self) -> Timings {
+        self.part().unwrap_or(Part::MX25R6435F).typical_timings()
+    }
+
+    pub(crate) fn verify_addr<E>(&self, addr: u32) -> Result<u32, Error<E>> {
+        if addr > self.size {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(addr)
+    }
+
+    /// Start waiting for the pending operation, limited to its maximum duration.
+    /// Without a known operation, the chip is only expected to be busy with a short register write
+    /// and the typical duration is unknown (0)
+    pub(crate) fn wait(&self) -> Wait {
+        let limit_us = self
+            .timings()
+            .duration_us(self.pending.unwrap_or(Operation::WriteRegister));
+        let typical_us = self
+            .pending
+            .map_or(0, |operation| self.typical_timings().duration_us(operation));
+        Wait {
+            limit_us,
+            typical_us,
+            elapsed_us: 0,
+            polls: 0,
+        }
+    }
+
+    /// Decode the wip bit of the status register, the pending operation is done once the chip is ready.
+    /// Returns `true` if the security register must be read for the failure flags of the pending program or erase
+    /// before it's forgotten, see [`Self::failure_checked`]
+    pub(crate) fn status_polled<E>(&mut self, status: StatusRegister) -> Result<bool, Error<E>> {
+        if status.wip_bit {
+            return Err(Error::Busy);
+        }
+        if self.failure_detection && self.token_pending() {
+            return Ok(true);
+        }
+        self.pending = None;
+        Ok(false)
+    }
+
+    /// Decode the failure flags of the program or erase found completed by [`Self::status_polled`], it's forgotten
+    /// either way
+    pub(crate) fn failure_checked<E>(
+        &mut self,
+        security: SecurityRegister,
+    ) -> Result<(), Error<E>> {
+        self.pending = None;
+        self.check_failure(security, self.pending_addr)
+    }
+
+    /// A program or erase is completed through its token, the next write must be refused while it runs.
+    /// The register writes are waited for instead
+    pub(crate) fn token_pending(&self) -> bool {
+        self.pending
+            .is_some_and(|operation| operation != Operation::WriteRegister)
+    }
+
+    /// If the program or erase pending or suspended isn't `operation` at `addr`, whose token is then stale: its operation
+    /// completed and waiting would wait for the other one. Any token is accepted while nothing is pending
+    pub(crate) fn superseded(&self, operation: Operation, addr: u32) -> bool {
+        let current = match self.suspended {
+            Some(suspended) => Some(suspended),
+            None => self.pending.filter(|_| self.token_pending()),
+        };
+        current.is_some_and(|current| (current, self.pending_addr) != (operation, addr))
+    }
+
+    /// Record the operation started at `addr`
+    pub(crate) fn started(&mut self, operation: Operation, addr: u32) {
+        self.pending = Some(operation);
+        self.pending_addr = addr;
+    }
+
+    /// Decode the failure flags of the security register once the program or erase at `addr` completed
+    pub(crate) fn check_failure<E>(
+        &self,
+        security: SecurityRegister,
+        addr: u32,
+    ) -> Result<(), Error<E>> {
+        if security.program_failed {
+            return Err(Error::ProgramFailed { addr });
+        }
+        if security.erase_failed {
+            return Err(Error::EraseFailed { addr });
+        }
+        Ok(())
+    }
+
+    /// A secure OTP guard was dropped without exiting, the exit can't be sent right away
+    pub(crate) fn otp_guard_dropped(&mut self) {
+        self.otp_exit_pending = true;
+    }
+
+    /// If the exit of the secured OTP left by a dropped guard must be sent before the next instruction,
+    /// once the ongoing operation completes
+    pub(crate) fn otp_exit_needed(&self) -> bool {
+        self.otp_exit_pending
+    }
+
+    /// The wait and the exit of the secured OTP gave `res`, the exit is retried before the next instruction if it failed
+    pub(crate) fn otp_exit_flushed<E>(
+        &mut self,
+        res: Result<(), Error<E>>,
+    ) -> Result<(), Error<E>> {
+        self.otp_exit_pending = res.is_err();
+        res
+    }
+
+    /// If the configuration register must be read to know the dummy cycles of `mode`
+    pub(crate) fn needs_dummy_cycle(&self, mode: ReadMode) -> bool {
+        matches!(mode, ReadMode::DualIo | ReadMode::QuadIo) && self.dummy_cycle.is_none()
+    }
+
+    /// If the quad enable bit must be set before an instruction using `width` lines
+    pub(crate) fn needs_quad_enable(&self, width: BusWidth) -> bool {
+        width == BusWidth::Quad && !self.quad_enabled
+    }
+
+    /// The read instruction of `mode` at `addr`, the dummy cycle bit must be known for the 2READ and 4READ instructions
+    pub(crate) fn read_instruction<E>(
+        &self,
+        mode: ReadMode,
+        addr: u32,
+    ) -> Result<Instruction, Error<E>> {
+        let dummy_cycle = self.dummy_cycle.unwrap_or(false);
+        let instruction = match mode {
+            ReadMode::Normal => Command::Read.instruction(),
+            ReadMode::Fast => Command::ReadF.instruction().with_dummy_cycles(8),
+            ReadMode::DualOutput => Command::ReadD
+                .instruction()
+                .with_dummy_cycles(8)
+                .with_data_width(BusWidth::Dual),
+            ReadMode::DualIo => Command::Read2
+                .instruction()
+                .with_address_width(BusWidth::Dual)
+                .with_dummy_cycles(if dummy_cycle { 8 } else { 4 })
+                .with_data_width(BusWidth::Dual),
+            ReadMode::QuadOutput => Command::ReadQ
+                .instruction()
+                .with_dummy_cycles(8)
+                .with_data_width(BusWidth::Quad),
+            ReadMode::QuadIo => Command::Read4
+                .instruction()
+                .with_address_width(BusWidth::Quad)
+                .with_dummy_cycles(if dummy_cycle { 10 } else { 6 })
+                .with_data_width(BusWidth::Quad),
+        };
+        self.addressed(instruction, addr)
+    }
+
+    pub(crate) fn sfdp_instruction<E>(&self, addr: u32) -> Result<Instruction, Error<E>> {
+        self.addressed(Command::ReadSfdp.instruction().with_dummy_cycles(8), addr)
+    }
+
+    /// Number of bytes of the Basic Flash Parameter Table read from the SFDP space
+    pub(crate) fn basic_flash_parameters_len(param: &ParameterHeader) -> usize {
+        (param.length as usize).min(BASIC_FLASH_PARAMETER_MAX_DWORDS) * 4
+    }
+
+    /// Check that the burst length wraps the reads
+    pub(crate) fn check_wrapped<E>(&self) -> Result<(), Error<E>> {
+        if self.burst_length == BurstLength::Disabled {
+            return Err(Error::Value);
+        }
+        Ok(())
+    }
+
+    /// Address range of the erase in progress, `None` if no erase is in progress
+    pub(crate) fn erasing_range(&self) -> Option<Range<u32>> {
+        let size = match self.pending? {
+            Operation::SectorErase => SECTOR_SIZE,
+            Operation::Block32Erase => BLOCK32_SIZE,
+            Operation::Block64Erase => BLOCK64_SIZE,
+            Operation::ChipErase => return Some(0..self.capacity() as u32),
+            Operation::Program | Operation::WriteRegister => return None,
+        };
+        Some(self.pending_addr..self.pending_addr + size)
+    }
+
+    /// The suspend of the pending operation was sent, it's set aside until the resume
+    pub(crate) fn suspend_sent(&mut self) {
+        self.suspended = self.pending.take();
+    }
+
+    /// Decode the registers read once the suspend latency elapsed, the erase is forgotten if it completed before the
+    /// suspend. Returns [`Error::Timeout`] if the chip is still busy
+    pub(crate) fn suspend_checked<E>(
+        &mut self,
+        status: StatusRegister,
+        security: SecurityRegister,
+    ) -> Result<(), Error<E>> {
+        if status.wip_bit {
+            return Err(Error::Timeout);
+        }
+        if !security.erase_suspended {
+            self.suspended = None;
+        }
+        Ok(())
+    }
+
+    /// If an operation was suspended and must be resumed
+    pub(crate) fn resume_needed(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// The resume was sent, the suspended operation is pending again
+    pub(crate) fn resumed(&mut self) {
+        if let Some(operation) = self.suspended.take() {
+            self.pending = Some(operation);
+        }
+    }
+
+    /// Refuse the reads of `len` bytes at `addr` overlapping the `erasing` range
+    pub(crate) fn check_outside_erase<E>(
+        erasing: &Range<u32>,
+        addr: u32,
+        len: usize,
+    ) -> Result<(), Error<E>> {
+        let end = addr.saturating_add(len as u32);
+        if addr < erasing.end && erasing.start < end {
+            return Err(Error::Busy);
+        }
+        Ok(())
+    }
+
+    /// The page program instruction at `addr` with the address and data on `width` lines, PP or 4PP
+    pub(crate) fn program_instruction<E>(
+        &self,
+        addr: u32,
+        width: BusWidth,
+    ) -> Result<Instruction, Error<E>> {
+        let instruction = match width {
+            BusWidth::Quad => Command::ProgramPage4
+                .instruction()
+                .with_address_width(BusWidth::Quad)
+                .with_data_width(BusWidth::Quad),
+            BusWidth::Single | BusWidth::Dual => Command::ProgramPage.instruction(),
+        };
+        self.addressed(instruction, addr)
+    }
+
+    /// The erase instruction of `step`, the address must be aligned on the erased size
+    pub(crate) fn erase_instruction<E>(&self, step: EraseStep) -> Result<Instruction, Error<E>> {
+        if !step.addr().is_multiple_of(step.size()) {
+            return Err(Error::NotAligned);
+        }
+        let command = match step {
+            EraseStep::Sector(_) => Command::SectorErase,
+            EraseStep::Block32(_) => Command::BlockErase32,
+            EraseStep::Block64(_) => Command::BlockErase,
+        };
+        let addr = self.verify_addr(step.addr())?;
+        Ok(command.instruction().with_address(addr, BusWidth::Single))
+    }
+
+    /// Check that `len` bytes at `addr` fit in the secured OTP
+    pub(crate) fn check_otp<E>(addr: u32, len: usize) -> Result<(), Error<E>> {
+        crate::check_write(SECURE_OTP_SIZE as usize, addr, len)
+    }
+
+    /// Decode the configuration register and cache the bits the instructions depend on
+    pub(crate) fn configuration_read(&mut self, bytes: [u8; 2]) -> ConfigurationRegister {
+        let config = ConfigurationRegister::from(bytes);
+        self.dummy_cycle = Some(config.dummmy_cycle);
+        self.power_mode = Some(config.power_mode);
+        config
+    }
+
+    /// Payload of the status register write, the configuration register is left untouched
+    pub(crate) fn encode_status<E>(status: StatusRegister) -> Result<[u8; 1], Error<E>> {
+        Self::check_status(&status)?;
+        Ok([status.into()])
+    }
+
+    /// Payload of the status and configuration registers write
+    pub(crate) fn encode_status_configuration<E>(
+        status: StatusRegister,
+        config: ConfigurationRegister,
+    ) -> Result<[u8; 3], Error<E>> {
+        Self::check_status(&status)?;
+        let config_bytes: [u8; 2] = config.into();
+        Ok([status.into(), config_bytes[0], config_bytes[1]])
+    }
+
+    fn check_status<E>(status: &StatusRegister) -> Result<(), Error<E>> {
+        if status.protected_block > 0x0F {
+            return Err(Error::Value);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn status_written(&mut self, status: StatusRegister) {
+        self.pending = Some(Operation::WriteRegister);
+        self.quad_enabled = status.quad_enable;
+    }
+
+    pub(crate) fn status_configuration_written(
+        &mut self,
+        status: StatusRegister,
+        config: ConfigurationRegister,
+    ) {
+        self.status_written(status);
+        self.dummy_cycle = Some(config.dummmy_cycle);
+        self.power_mode = Some(config.power_mode);
+    }
+
+    /// The registers protecting the smallest area covering `range`, at the end of the array selected by `area` or by the
+    /// current top/bottom bit if `None`. The configuration register is only returned if the top/bottom bit changes.
+    /// Returns [`Error::Value`] if no setting of the area covers the range, see [`BlockProtection::for_range`], or if the
+    /// top is requested once the bit is set
+    pub(crate) fn protect_range<E>(
+        &self,
+        range: Range<u32>,
+        area: Option<ProtectedArea>,
+        mut status: StatusRegister,
+        mut config: ConfigurationRegister,
+    ) -> Result<
+        (
+            BlockProtection,
+            StatusRegister,
+            Option<ConfigurationRegister>,
+        ),
+        Error<E>,
+    > {
+        let area = area.unwrap_or(config.protected_section);
+        // The top/bottom bit is one time programmable
+        if area == ProtectedArea::Top && config.protected_section == ProtectedArea::Bottom {
+            return Err(Error::Value);
+        }
+        let protection =
+            BlockProtection::for_range(self.capacity(), range, area).ok_or(Error::Value)?;
+
+        status.protected_block = protection.protected_block;
+        if protection.area == config.protected_section {
+            return Ok((protection, status, None));
+        }
+        config.protected_section = protection.area;
+        Ok((protection, status, Some(config)))
+    }
+
+    /// If a transfer of `len` bytes reaches the managed power mode `threshold`
+    pub(crate) fn reaches_threshold(len: usize, threshold: Option<usize>) -> bool {
+        threshold.is_some_and(|threshold| len >= threshold)
+    }
+
+    /// The reset restores the volatile bits of the registers
+    pub(crate) fn reset(&mut self) {
+        self.quad_enabled = false;
+        self.burst_length = BurstLength::Disabled;
+        self.power_mode = None;
+        self.dummy_cycle = None;
+    }
+
+    pub(crate) fn electronic_id_instruction() -> Instruction {
+        Command::ReadElectronicId
+            .instruction()
+            .with_dummy_cycles(24)
+    }
+
+    pub(crate) fn manufacturer_id_instruction() -> Instruction {
+        // An address of 0 outputs the manufacturer ID first
+        Command::ReadManufacturerId
+            .instruction()
+            .with_address(0, BusWidth::Single)
+    }
+
+    pub(crate) fn decode_identification(
+        id: [u8; 3],
+    ) -> (ManufacturerId, MemoryType, MemoryDensity) {
+        (
+            ManufacturerId(id[0]),
+            MemoryType(id[1]),
+            MemoryDensity(id[2]),
+        )
+    }
+
+    /// Set the address of `instruction` once verified
+    fn addressed<E>(&self, instruction: Instruction, addr: u32) -> Result<Instruction, Error<E>> {
+        let addr = self.verify_addr(addr)?;
+        let address_width = instruction.address_width;
+        Ok(instruction.with_address(addr, address_width))
+    }
+}
+
+/// Time accounting of a wait for the wip bit, the drivers only poll and run the delays it returns.
+/// Only the delays are counted, at least 1µs each, the time spent polling on the bus comes on top of the limit
+pub(crate) struct Wait {
+    limit_us: u32,
+    /// Typical duration of the operation waited for, 0 if unknown
+    typical_us: u32,
+    elapsed_us: u32,
+    /// Number of polls that found the chip busy
+    polls: u32,
+}
+
+impl Wait {
+    /// Fixed delay between the polls of the blocking driver, the limit spread over [`WAIT_POLLS`] polls
+    pub(crate) fn interval_us(&self) -> u32 {
+        (self.limit_us / WAIT_POLLS).clamp(1, MAX_WAIT_INTERVAL_US)
+    }
+
+    /// A poll found the chip busy, returns the delay chosen by `strategy` before the next poll, cut to the time left.
+    /// Returns [`Error::Timeout`] once the limit is reached
+    pub(crate) fn busy<E>(&mut self, strategy: &mut impl PollStrategy) -> Result<u32, Error<E>> {
+        if self.elapsed_us >= self.limit_us {
+            return Err(Error::Timeout);
+        }
+        self.polls += 1;
+        let delay_us = strategy
+            .delay_us(self.typical_us, self.polls)
+            .min(self.limit_us - self.elapsed_us);
+        // A delay of 0 only yields, it's counted as 1µs so that the wait still times out
+        self.elapsed_us += delay_us.max(1);
+        Ok(delay_us)
+    }
+}
+
+/// Split `bytes` programmed from `addr` in chunks that don't cross a page boundary, a page program wraps around its page
+pub(crate) fn page_chunks(mut addr: u32, mut bytes: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    core::iter::from_fn(move || {
+        if bytes.is_empty() {
+            return None;
+        }
+        let chunk_len = ((PAGE_SIZE - addr % PAGE_SIZE) as usize).min(bytes.len());
+        let (chunk, rest) = bytes.split_at(chunk_len);
+        let item = (addr, chunk);
+        bytes = rest;
+        addr += chunk_len as u32;
+        Some(item)
+    })
+}
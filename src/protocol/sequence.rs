@@ -0,0 +1,459 @@
+//! The operations made of several exchanges with the chip, sequenced once for both drivers.
+//!
+//! A [`Sequence`] is a state machine: given the [`Outcome`] of the previous step it returns the next [`Step`],
+//! the drivers only run the [`Io`] and give its outcome back. The first error of a step is the result of the
+//! sequence, the steps after a failure only undo what must not be left behind, e.g. a suspended erase.
+
+use core::ops::Range;
+
+use crate::{
+    bus::ReadMode,
+    command::Command,
+    error::Error,
+    protection::BlockProtection,
+    register::*,
+    timing::{Operation, ERASE_SUSPEND_LATENCY_US},
+};
+
+use super::Protocol;
+
+/// An exchange with the chip run by the drivers, its result is given back as an [`Outcome`]
+pub(crate) enum Io {
+    /// Send the command without data
+    Send(Command),
+    ReadStatus,
+    ReadConfiguration,
+    ReadSecurity,
+    /// Write the status register, the write enable is sent first
+    WriteStatus(StatusRegister),
+    /// Write the status and configuration registers, the write enable is sent first
+    WriteStatusConfiguration(StatusRegister, ConfigurationRegister),
+    /// Read the wip bit once, see `poll_wip`
+    Poll,
+    /// Wait for the chip to be ready, see `wait_wip`
+    Wait,
+    Delay(u32),
+    /// Read the buffer given with the sequence at the address, with the instruction of the mode
+    Read(ReadMode, u32),
+}
+
+/// What a [`Sequence`] asks the drivers next
+pub(crate) enum Step<T> {
+    Io(Io),
+    /// The sequence completed, the first error of its steps is returned instead of the output if any
+    Done(T),
+    /// The sequence stopped after a failed step, the first error is returned
+    Abort,
+}
+
+/// Result of the previous [`Io`], given to [`Sequence::step`]
+pub(crate) enum Outcome {
+    /// Nothing ran yet
+    Start,
+    /// The exchange completed, for [`Io::Poll`] the chip was found ready
+    Completed,
+    /// The chip was found busy by [`Io::Poll`]
+    Busy,
+    Status(StatusRegister),
+    Configuration(ConfigurationRegister),
+    Security(SecurityRegister),
+    /// The exchange, or the decoding of the previous one, failed
+    Failed,
+}
+
+/// An operation made of several exchanges with the chip
+pub(crate) trait Sequence {
+    type Output;
+
+    /// The next step once the previous one gave `outcome`.
+    /// An error fails the sequence as a failed exchange would, the next step is asked with [`Outcome::Failed`]
+    fn step<E>(
+        &mut self,
+        protocol: &mut Protocol,
+        outcome: Outcome,
+    ) -> Result<Step<Self::Output>, Error<E>>;
+}
+
+/// Read while an erase is in progress, the erase is suspended for the read and resumed afterwards even if the read
+/// fails. Without an erase in progress, only the read is sent
+pub(crate) struct ReadWhileErasing {
+    addr: u32,
+    len: usize,
+    state: ReadWhileErasingState,
+}
+
+#[derive(Clone, Copy)]
+enum ReadWhileErasingState {
+    Start,
+    Polling,
+    Suspending,
+    Suspended,
+    ReadingStatus,
+    ReadingSecurity(StatusRegister),
+    Reading { suspended: bool },
+    Finished,
+}
+
+impl ReadWhileErasing {
+    pub(crate) fn new(addr: u32, len: usize) -> Self {
+        ReadWhileErasing {
+            addr,
+            len,
+            state: ReadWhileErasingState::Start,
+        }
+    }
+
+    fn read(&mut self, protocol: &Protocol, suspended: bool) -> Step<()> {
+        self.state = ReadWhileErasingState::Reading { suspended };
+        Step::Io(Io::Read(protocol.read_mode, self.addr))
+    }
+
+    /// Whatever the read gives, the erase must not be left suspended
+    fn resume(&mut self, protocol: &mut Protocol) -> Step<()> {
+        self.state = ReadWhileErasingState::Finished;
+        if !protocol.resume_needed() {
+            return Step::Done(());
+        }
+        protocol.resumed();
+        Step::Io(Io::Send(Command::ProgramEraseResume))
+    }
+}
+
+impl Sequence for ReadWhileErasing {
+    type Output = ();
+
+    fn step<E>(&mut self, protocol: &mut Protocol, outcome: Outcome) -> Result<Step<()>, Error<E>> {
+        use ReadWhileErasingState as State;
+        Ok(match (self.state, outcome) {
+            (State::Start, _) => {
+                if protocol.erasing_range().is_none() {
+                    return Ok(self.read(protocol, false));
+                }
+                self.state = State::Polling;
+                Step::Io(Io::Poll)
+            }
+            (State::Polling, Outcome::Busy) => {
+                self.state = State::Finished;
+                if let Some(erasing) = protocol.erasing_range() {
+                    Protocol::check_outside_erase(&erasing, self.addr, self.len)?;
+                }
+                self.state = State::Suspending;
+                Step::Io(Io::Send(Command::ProgramEraseSuspend))
+            }
+            // The erase already completed
+            (State::Polling, Outcome::Completed) => self.read(protocol, false),
+            (State::Suspending, Outcome::Completed) => {
+                protocol.suspend_sent();
+                self.state = State::Suspended;
+                Step::Io(Io::Delay(ERASE_SUSPEND_LATENCY_US))
+            }
+            (State::Suspended, Outcome::Completed) => {
+                self.state = State::ReadingStatus;
+                Step::Io(Io::ReadStatus)
+            }
+            (State::ReadingStatus, Outcome::Status(status)) => {
+                self.state = State::ReadingSecurity(status);
+                Step::Io(Io::ReadSecurity)
+            }
+            (State::ReadingSecurity(status), Outcome::Security(security)) => {
+                self.state = State::Reading { suspended: true };
+                protocol.suspend_checked(status, security)?;
+                self.read(protocol, true)
+            }
+            (
+                State::ReadingStatus
+                | State::ReadingSecurity(_)
+                | State::Reading { suspended: true },
+                _,
+            ) => self.resume(protocol),
+            (State::Reading { suspended: false } | State::Finished, _) => Step::Done(()),
+            (State::Polling | State::Suspending | State::Suspended, _) => Step::Abort,
+        })
+    }
+}
+
+/// Switch the power mode with a write of the registers and wait for it, or do nothing without a mode
+pub(crate) struct SetPowerMode {
+    mode: Option<PowerMode>,
+    state: SetPowerModeState,
+}
+
+enum SetPowerModeState {
+    Start,
+    ReadingStatus,
+    ReadingConfiguration(StatusRegister),
+    Writing,
+    Waiting,
+}
+
+impl SetPowerMode {
+    pub(crate) fn new(mode: PowerMode) -> Self {
+        Self::with(Some(mode))
+    }
+
+    /// Switch back to the ultra low power mode after a [`PowerBoost`] that switched the mode
+    pub(crate) fn restore(boosted: bool) -> Self {
+        Self::with(boosted.then_some(PowerMode::UltraLowPower))
+    }
+
+    fn with(mode: Option<PowerMode>) -> Self {
+        SetPowerMode {
+            mode,
+            state: SetPowerModeState::Start,
+        }
+    }
+}
+
+impl Sequence for SetPowerMode {
+    type Output = ();
+
+    fn step<E>(&mut self, _: &mut Protocol, outcome: Outcome) -> Result<Step<()>, Error<E>> {
+        use SetPowerModeState as State;
+        let Some(mode) = self.mode else {
+            return Ok(Step::Done(()));
+        };
+        let (state, io) = match (&self.state, outcome) {
+            (State::Start, _) => (State::ReadingStatus, Io::ReadStatus),
+            (State::ReadingStatus, Outcome::Status(status)) => {
+                (State::ReadingConfiguration(status), Io::ReadConfiguration)
+            }
+            (State::ReadingConfiguration(status), Outcome::Configuration(mut config)) => {
+                config.power_mode = mode;
+                (
+                    State::Writing,
+                    Io::WriteStatusConfiguration(*status, config),
+                )
+            }
+            (State::Writing, Outcome::Completed) => (State::Waiting, Io::Wait),
+            (State::Waiting, Outcome::Completed) => return Ok(Step::Done(())),
+            _ => return Ok(Step::Abort),
+        };
+        self.state = state;
+        Ok(Step::Io(io))
+    }
+}
+
+/// Switch to the high performance mode if a transfer reaches the threshold of the managed power mode.
+/// Outputs if the ultra low power mode must be restored afterwards, see [`SetPowerMode::restore`]
+pub(crate) struct PowerBoost {
+    len: usize,
+    write: bool,
+    state: PowerBoostState,
+}
+
+enum PowerBoostState {
+    Start,
+    ReadingConfiguration,
+    Switching(SetPowerMode),
+}
+
+impl PowerBoost {
+    /// Boost for a read of `len` bytes
+    pub(crate) fn read(len: usize) -> Self {
+        Self::with(len, false)
+    }
+
+    /// Boost for a write of `len` bytes
+    pub(crate) fn write(len: usize) -> Self {
+        Self::with(len, true)
+    }
+
+    fn with(len: usize, write: bool) -> Self {
+        PowerBoost {
+            len,
+            write,
+            state: PowerBoostState::Start,
+        }
+    }
+
+    /// A chip already in the high performance mode is left untouched
+    fn switch<E>(
+        &mut self,
+        protocol: &mut Protocol,
+        mode: PowerMode,
+    ) -> Result<Step<bool>, Error<E>> {
+        if mode == PowerMode::HighPerformance {
+            return Ok(Step::Done(false));
+        }
+        self.state = PowerBoostState::Switching(SetPowerMode::new(PowerMode::HighPerformance));
+        self.step(protocol, Outcome::Start)
+    }
+}
+
+impl Sequence for PowerBoost {
+    type Output = bool;
+
+    fn step<E>(
+        &mut self,
+        protocol: &mut Protocol,
+        outcome: Outcome,
+    ) -> Result<Step<bool>, Error<E>> {
+        match (&mut self.state, outcome) {
+            (PowerBoostState::Start, _) => {
+                let threshold = protocol.power_thresholds.map(|thresholds| {
+                    if self.write {
+                        thresholds.write
+                    } else {
+                        thresholds.read
+                    }
+                });
+                if !Protocol::reaches_threshold(self.len, threshold) {
+                    return Ok(Step::Done(false));
+                }
+                match protocol.power_mode {
+                    Some(mode) => self.switch(protocol, mode),
+                    None => {
+                        self.state = PowerBoostState::ReadingConfiguration;
+                        Ok(Step::Io(Io::ReadConfiguration))
+                    }
+                }
+            }
+            (PowerBoostState::ReadingConfiguration, Outcome::Configuration(config)) => {
+                self.switch(protocol, config.power_mode)
+            }
+            (PowerBoostState::Switching(switch), outcome) => {
+                Ok(match switch.step(protocol, outcome)? {
+                    Step::Io(io) => Step::Io(io),
+                    Step::Done(()) => Step::Done(true),
+                    Step::Abort => Step::Abort,
+                })
+            }
+            _ => Ok(Step::Abort),
+        }
+    }
+}
+
+/// Protect the smallest area covering a range, see [`Protocol::protect_range`]
+pub(crate) struct Protect {
+    range: Range<u32>,
+    area: Option<ProtectedArea>,
+    state: ProtectState,
+}
+
+enum ProtectState {
+    Start,
+    ReadingStatus,
+    ReadingConfiguration(StatusRegister),
+    Writing(BlockProtection),
+    Finished,
+}
+
+impl Protect {
+    /// Protect `range` at the end of the array selected by `area`, or by the current top/bottom bit if `None`
+    pub(crate) fn new(range: Range<u32>, area: Option<ProtectedArea>) -> Self {
+        Protect {
+            range,
+            area,
+            state: ProtectState::Start,
+        }
+    }
+}
+
+impl Sequence for Protect {
+    type Output = BlockProtection;
+
+    fn step<E>(
+        &mut self,
+        protocol: &mut Protocol,
+        outcome: Outcome,
+    ) -> Result<Step<BlockProtection>, Error<E>> {
+        let (state, io) = match (&self.state, outcome) {
+            (ProtectState::Start, _) => (ProtectState::ReadingStatus, Io::ReadStatus),
+            (ProtectState::ReadingStatus, Outcome::Status(status)) => (
+                ProtectState::ReadingConfiguration(status),
+                Io::ReadConfiguration,
+            ),
+            (ProtectState::ReadingConfiguration(status), Outcome::Configuration(config)) => {
+                let status = *status;
+                self.state = ProtectState::Finished;
+                let (protection, status, config) =
+                    protocol.protect_range(self.range.clone(), self.area, status, config)?;
+                let io = match config {
+                    None => Io::WriteStatus(status),
+                    Some(config) => Io::WriteStatusConfiguration(status, config),
+                };
+                (ProtectState::Writing(protection), io)
+            }
+            (ProtectState::Writing(protection), Outcome::Completed) => {
+                return Ok(Step::Done(*protection))
+            }
+            _ => return Ok(Step::Abort),
+        };
+        self.state = state;
+        Ok(Step::Io(io))
+    }
+}
+
+/// Poll or wait for the program or erase of a token, the failure flags are checked once it completed
+/// if the failure detection is enabled. [`Error::Value`] is returned for a stale token, see [`Protocol::superseded`]
+pub(crate) struct Completion {
+    operation: Operation,
+    addr: u32,
+    wait: bool,
+    state: CompletionState,
+}
+
+enum CompletionState {
+    Start,
+    Polling { pending: bool },
+    Checking,
+    Finished,
+}
+
+impl Completion {
+    /// Poll once, [`Error::Busy`] is returned while the operation is in progress
+    pub(crate) fn poll(operation: Operation, addr: u32) -> Self {
+        Self::with(operation, addr, false)
+    }
+
+    /// Wait for the operation to complete
+    pub(crate) fn wait(operation: Operation, addr: u32) -> Self {
+        Self::with(operation, addr, true)
+    }
+
+    fn with(operation: Operation, addr: u32, wait: bool) -> Self {
+        Completion {
+            operation,
+            addr,
+            wait,
+            state: CompletionState::Start,
+        }
+    }
+}
+
+impl Sequence for Completion {
+    type Output = ();
+
+    fn step<E>(&mut self, protocol: &mut Protocol, outcome: Outcome) -> Result<Step<()>, Error<E>> {
+        Ok(match (&self.state, outcome) {
+            (CompletionState::Start, _) => {
+                if protocol.superseded(self.operation, self.addr) {
+                    self.state = CompletionState::Finished;
+                    return Err(Error::Value);
+                }
+                // The flags are read by the poll finding the operation completed, unless another call found it first
+                self.state = CompletionState::Polling {
+                    pending: protocol.token_pending(),
+                };
+                Step::Io(if self.wait { Io::Wait } else { Io::Poll })
+            }
+            (CompletionState::Polling { .. }, Outcome::Busy) => {
+                self.state = CompletionState::Finished;
+                return Err(Error::Busy);
+            }
+            (CompletionState::Polling { pending }, Outcome::Completed) => {
+                if *pending || !protocol.failure_detection {
+                    return Ok(Step::Done(()));
+                }
+                self.state = CompletionState::Checking;
+                Step::Io(Io::ReadSecurity)
+            }
+            (CompletionState::Checking, Outcome::Security(security)) => {
+                self.state = CompletionState::Finished;
+                protocol.check_failure(security, self.addr)?;
+                Step::Done(())
+            }
+            _ => Step::Abort,
+        })
+    }
+}
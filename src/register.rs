@@ -1,3 +1,5 @@
+use core::fmt;
+
 use bit::BitIndex;
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -20,8 +22,28 @@ pub struct ElectronicId(pub u8);
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DeviceId(pub u8);
 
+/// Display the identification bytes in hexadecimal
+macro_rules! display_hex {
+    ($($id:ty),*) => {
+        $(impl fmt::Display for $id {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:#04x}", self.0)
+            }
+        })*
+    };
+}
+
+display_hex!(
+    ManufacturerId,
+    MemoryType,
+    MemoryDensity,
+    ElectronicId,
+    DeviceId
+);
+
+/// The status register, the write in progress and write enable latch bits are read only
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct StatusRegister {
     pub write_protect_disable: bool,
     pub quad_enable: bool,
@@ -42,8 +64,34 @@ impl From<u8> for StatusRegister {
     }
 }
 
+impl From<StatusRegister> for u8 {
+    fn from(val: StatusRegister) -> u8 {
+        let mut reg = 0;
+        reg.set_bit(7, val.write_protect_disable);
+        reg.set_bit(6, val.quad_enable);
+        reg.set_bit_range(2..6, val.protected_block);
+        reg.set_bit(1, val.write_enable_latch);
+        reg.set_bit(0, val.wip_bit);
+        reg
+    }
+}
+
+impl fmt::Display for StatusRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SRWD={} QE={} BP={:#x} WEL={} WIP={}",
+            u8::from(self.write_protect_disable),
+            u8::from(self.quad_enable),
+            self.protected_block,
+            u8::from(self.write_enable_latch),
+            u8::from(self.wip_bit)
+        )
+    }
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProtectedArea {
     Top,
     Bottom,
@@ -65,9 +113,17 @@ impl From<ProtectedArea> for bool {
         }
     }
 }
+impl fmt::Display for ProtectedArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProtectedArea::Top => "top",
+            ProtectedArea::Bottom => "bottom",
+        })
+    }
+}
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PowerMode {
     UltraLowPower,
     HighPerformance,
@@ -89,17 +145,58 @@ impl From<PowerMode> for bool {
         }
     }
 }
+impl fmt::Display for PowerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PowerMode::UltraLowPower => "ultra low power",
+            PowerMode::HighPerformance => "high performance",
+        })
+    }
+}
 
+/// The two bytes configuration register
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ConfigurationRegister {
     pub dummmy_cycle: bool,
     pub protected_section: ProtectedArea,
     pub power_mode: PowerMode,
 }
 
+impl From<[u8; 2]> for ConfigurationRegister {
+    fn from(val: [u8; 2]) -> ConfigurationRegister {
+        ConfigurationRegister {
+            dummmy_cycle: val[0].bit(6),
+            protected_section: val[0].bit(3).into(),
+            power_mode: val[1].bit(1).into(),
+        }
+    }
+}
+
+impl From<ConfigurationRegister> for [u8; 2] {
+    fn from(val: ConfigurationRegister) -> [u8; 2] {
+        let mut reg = [0; 2];
+        reg[0].set_bit(6, val.dummmy_cycle);
+        reg[0].set_bit(3, val.protected_section.into());
+        reg[1].set_bit(1, val.power_mode.into());
+        reg
+    }
+}
+
+impl fmt::Display for ConfigurationRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DC={} TB={} L/H={}",
+            u8::from(self.dummmy_cycle),
+            self.protected_section,
+            self.power_mode
+        )
+    }
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SecurityRegister {
     pub erase_failed: bool,
     pub program_failed: bool,
@@ -108,3 +205,92 @@ pub struct SecurityRegister {
     pub locked_down: bool,
     pub secured_otp: bool,
 }
+
+impl From<u8> for SecurityRegister {
+    fn from(val: u8) -> SecurityRegister {
+        SecurityRegister {
+            erase_failed: val.bit(6),
+            program_failed: val.bit(5),
+            erase_suspended: val.bit(3),
+            program_suspended: val.bit(2),
+            locked_down: val.bit(1),
+            secured_otp: val.bit(0),
+        }
+    }
+}
+
+impl From<SecurityRegister> for u8 {
+    fn from(val: SecurityRegister) -> u8 {
+        let mut reg = 0;
+        reg.set_bit(6, val.erase_failed);
+        reg.set_bit(5, val.program_failed);
+        reg.set_bit(3, val.erase_suspended);
+        reg.set_bit(2, val.program_suspended);
+        reg.set_bit(1, val.locked_down);
+        reg.set_bit(0, val.secured_otp);
+        reg
+    }
+}
+
+impl fmt::Display for SecurityRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "E_FAIL={} P_FAIL={} ESB={} PSB={} LDSO={} SOTP={}",
+            u8::from(self.erase_failed),
+            u8::from(self.program_failed),
+            u8::from(self.erase_suspended),
+            u8::from(self.program_suspended),
+            u8::from(self.locked_down),
+            u8::from(self.secured_otp)
+        )
+    }
+}
+
+/// Wrap around length of the burst reads, written with the set burst length instruction.
+/// The wrap around is disabled at power up
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BurstLength {
+    Wrap8,
+    Wrap16,
+    Wrap32,
+    Wrap64,
+    #[default]
+    Disabled,
+}
+
+impl BurstLength {
+    /// Number of bytes read before wrapping around, `None` if the wrap around is disabled
+    pub const fn wrap_size(&self) -> Option<u32> {
+        match self {
+            BurstLength::Wrap8 => Some(8),
+            BurstLength::Wrap16 => Some(16),
+            BurstLength::Wrap32 => Some(32),
+            BurstLength::Wrap64 => Some(64),
+            BurstLength::Disabled => None,
+        }
+    }
+}
+
+impl fmt::Display for BurstLength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.wrap_size() {
+            Some(size) => write!(f, "wrap around {} bytes", size),
+            None => f.write_str("no wrap around"),
+        }
+    }
+}
+
+impl From<BurstLength> for u8 {
+    fn from(val: BurstLength) -> Self {
+        match val {
+            BurstLength::Wrap8 => 0x00,
+            BurstLength::Wrap16 => 0x01,
+            BurstLength::Wrap32 => 0x02,
+            BurstLength::Wrap64 => 0x03,
+            // Bit 4 disables the wrap around
+            BurstLength::Disabled => 0x10,
+        }
+    }
+}
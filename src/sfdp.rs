@@ -0,0 +1,306 @@
+//! Decoding of the JESD216 Serial Flash Discoverable Parameters.
+//!
+//! The tables are read with the `read_sfdp` instruction of the drivers, see
+//! [`MX25R::read_basic_flash_parameters`](crate::blocking::MX25R::read_basic_flash_parameters)
+//! and [`AsyncMX25R::read_basic_flash_parameters`](crate::asynchronous::AsyncMX25R::read_basic_flash_parameters).
+//! Everything here only decodes byte buffers, so it can also be used on a dump of the tables.
+
+use bit::BitIndex;
+
+/// The `SFDP` signature, as read in little endian from the start of the table
+pub const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// Size of the SFDP header and of each parameter header in bytes
+pub const HEADER_SIZE: usize = 8;
+
+/// Parameter ID of the JEDEC Basic Flash Parameter Table
+pub const BASIC_FLASH_PARAMETER_ID: u16 = 0xFF00;
+
+/// Maximum number of dwords of the Basic Flash Parameter Table that are read by the drivers.
+/// This covers every field up to JESD216B, longer tables are truncated.
+pub const BASIC_FLASH_PARAMETER_MAX_DWORDS: usize = 16;
+
+/// Minimum number of dwords of a valid Basic Flash Parameter Table (JESD216 rev 0)
+pub const BASIC_FLASH_PARAMETER_MIN_DWORDS: usize = 9;
+
+fn dword(bytes: &[u8], index: usize) -> Option<u32> {
+    let start = index * 4;
+    let bytes = bytes.get(start..start + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The SFDP header located at address 0 of the SFDP space
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SfdpHeader {
+    pub minor_revision: u8,
+    pub major_revision: u8,
+    /// Index of the last parameter header, the NPH field which is zero based
+    pub last_parameter_header: u8,
+    pub access_protocol: u8,
+}
+
+impl SfdpHeader {
+    /// Decode the header, returns `None` if the signature doesn't match
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        if dword(bytes, 0)? != SFDP_SIGNATURE {
+            return None;
+        }
+        Some(SfdpHeader {
+            minor_revision: bytes[4],
+            major_revision: bytes[5],
+            last_parameter_header: bytes[6],
+            access_protocol: bytes[7],
+        })
+    }
+
+    /// Number of parameter headers following the SFDP header, from 1 to 256
+    pub fn parameter_headers(&self) -> u16 {
+        self.last_parameter_header as u16 + 1
+    }
+
+    /// Address of the parameter header at `index` in the SFDP space
+    pub fn parameter_header_address(index: u8) -> u32 {
+        (HEADER_SIZE * (index as usize + 1)) as u32
+    }
+}
+
+/// A parameter header, describing where a parameter table is located
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterHeader {
+    pub id: u16,
+    pub minor_revision: u8,
+    pub major_revision: u8,
+    /// Length of the table in dwords
+    pub length: u8,
+    /// Address of the table in the SFDP space
+    pub pointer: u32,
+}
+
+impl ParameterHeader {
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Self {
+        ParameterHeader {
+            id: u16::from_le_bytes([bytes[0], bytes[7]]),
+            minor_revision: bytes[1],
+            major_revision: bytes[2],
+            length: bytes[3],
+            pointer: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], 0]),
+        }
+    }
+
+    /// If the header points to the JEDEC Basic Flash Parameter Table
+    pub fn is_basic_flash_parameters(&self) -> bool {
+        self.id == BASIC_FLASH_PARAMETER_ID
+    }
+}
+
+/// Number of address bytes supported by the device
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressBytes {
+    Three,
+    ThreeOrFour,
+    Four,
+    Reserved,
+}
+
+impl From<u8> for AddressBytes {
+    fn from(val: u8) -> Self {
+        match val {
+            0b00 => AddressBytes::Three,
+            0b01 => AddressBytes::ThreeOrFour,
+            0b10 => AddressBytes::Four,
+            _ => AddressBytes::Reserved,
+        }
+    }
+}
+
+/// Parameters of a fast read instruction
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastRead {
+    pub opcode: u8,
+    pub dummy_cycles: u8,
+    pub mode_clocks: u8,
+}
+
+impl FastRead {
+    /// Decode the 16 bits descriptor used in the table
+    fn from_bits(val: u16) -> Self {
+        FastRead {
+            dummy_cycles: val.bit_range(0..5) as u8,
+            mode_clocks: val.bit_range(5..8) as u8,
+            opcode: val.bit_range(8..16) as u8,
+        }
+    }
+}
+
+/// Fast read instructions supported by the device, named by the `command-address-data` line widths
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FastReadModes {
+    pub read_1_1_2: Option<FastRead>,
+    pub read_1_2_2: Option<FastRead>,
+    pub read_1_1_4: Option<FastRead>,
+    pub read_1_4_4: Option<FastRead>,
+    pub read_2_2_2: Option<FastRead>,
+    pub read_4_4_4: Option<FastRead>,
+}
+
+/// An erase instruction supported by the device
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraseType {
+    /// Size erased in bytes
+    pub size: u32,
+    pub opcode: u8,
+    /// Typical erase time in microseconds, if reported
+    pub typical_time_us: Option<u32>,
+    /// Maximum erase time in microseconds, if reported
+    pub max_time_us: Option<u32>,
+}
+
+/// Typical program and chip erase times, only available from JESD216A
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramTimes {
+    pub page_program_typical_us: u32,
+    pub page_program_max_us: u32,
+    pub first_byte_program_typical_us: u32,
+    pub additional_byte_program_typical_us: u32,
+    pub chip_erase_typical_ms: u32,
+    pub chip_erase_max_ms: u32,
+}
+
+/// The decoded JEDEC Basic Flash Parameter Table
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicFlashParameters {
+    /// Density of the device in bits
+    pub density_bits: u64,
+    pub address_bytes: AddressBytes,
+    /// Opcode of the uniform 4kB erase, if supported
+    pub erase_4k_opcode: Option<u8>,
+    pub erase_types: [Option<EraseType>; 4],
+    pub fast_read: FastReadModes,
+    /// Page size in bytes, if reported (JESD216A and later)
+    pub page_size: Option<u32>,
+    /// Program and chip erase times, if reported (JESD216A and later)
+    pub program_times: Option<ProgramTimes>,
+}
+
+impl BasicFlashParameters {
+    /// Decode the table from its raw bytes, returns `None` if the table is too short
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let dw = |index: usize| dword(bytes, index);
+        if bytes.len() < BASIC_FLASH_PARAMETER_MIN_DWORDS * 4 {
+            return None;
+        }
+
+        let dw1 = dw(0)?;
+        let dw2 = dw(1)?;
+        let dw3 = dw(2)?;
+        let dw4 = dw(3)?;
+        let dw5 = dw(4)?;
+        let dw6 = dw(5)?;
+        let dw7 = dw(6)?;
+        let dw8 = dw(7)?;
+        let dw9 = dw(8)?;
+
+        let density_bits = if dw2.bit(31) {
+            1u64.checked_shl(dw2.bit_range(0..31))?
+        } else {
+            dw2 as u64 + 1
+        };
+
+        let erase_4k_opcode = if dw1.bit_range(0..2) == 0b01 {
+            Some(dw1.bit_range(8..16) as u8)
+        } else {
+            None
+        };
+
+        let upper = |val: u32| FastRead::from_bits(val.bit_range(16..32) as u16);
+        let lower = |val: u32| FastRead::from_bits(val.bit_range(0..16) as u16);
+        let fast_read = FastReadModes {
+            read_1_1_2: dw1.bit(16).then(|| lower(dw4)),
+            read_1_2_2: dw1.bit(20).then(|| upper(dw4)),
+            read_1_1_4: dw1.bit(22).then(|| upper(dw3)),
+            read_1_4_4: dw1.bit(21).then(|| lower(dw3)),
+            read_2_2_2: dw5.bit(0).then(|| upper(dw6)),
+            read_4_4_4: dw5.bit(4).then(|| upper(dw7)),
+        };
+
+        let erase_type = |val: u32| {
+            let size = val.bit_range(0..8);
+            (size != 0).then(|| EraseType {
+                size: 1u32 << size.min(31),
+                opcode: val.bit_range(8..16) as u8,
+                typical_time_us: None,
+                max_time_us: None,
+            })
+        };
+        let mut erase_types = [
+            erase_type(dw8),
+            erase_type(dw8 >> 16),
+            erase_type(dw9),
+            erase_type(dw9 >> 16),
+        ];
+
+        let mut page_size = None;
+        let mut program_times = None;
+        if let (Some(dw10), Some(dw11)) = (dw(9), dw(10)) {
+            let multiplier = 2 * (dw10.bit_range(0..4) + 1);
+            for (i, erase) in erase_types.iter_mut().enumerate() {
+                if let Some(erase) = erase {
+                    let field = dw10 >> (4 + 7 * i);
+                    let unit_us = match field.bit_range(5..7) {
+                        0b00 => 1_000,
+                        0b01 => 16_000,
+                        0b10 => 128_000,
+                        _ => 1_000_000,
+                    };
+                    let typical = (field.bit_range(0..5) + 1) * unit_us;
+                    erase.typical_time_us = Some(typical);
+                    erase.max_time_us = Some(typical.saturating_mul(multiplier));
+                }
+            }
+
+            let multiplier = 2 * (dw11.bit_range(0..4) + 1);
+            page_size = Some(1u32 << dw11.bit_range(4..8));
+            let page_unit_us = if dw11.bit(13) { 64 } else { 8 };
+            let byte_unit_us = |bit| if dw11.bit(bit) { 8 } else { 1 };
+            let chip_unit_ms = match dw11.bit_range(29..31) {
+                0b00 => 16,
+                0b01 => 256,
+                0b10 => 4_000,
+                _ => 64_000,
+            };
+            let page_program_typical_us = (dw11.bit_range(8..13) + 1) * page_unit_us;
+            let chip_erase_typical_ms = (dw11.bit_range(24..29) + 1) * chip_unit_ms;
+            program_times = Some(ProgramTimes {
+                page_program_typical_us,
+                page_program_max_us: page_program_typical_us * multiplier,
+                first_byte_program_typical_us: (dw11.bit_range(14..18) + 1) * byte_unit_us(18),
+                additional_byte_program_typical_us: (dw11.bit_range(19..23) + 1) * byte_unit_us(23),
+                chip_erase_typical_ms,
+                chip_erase_max_ms: chip_erase_typical_ms.saturating_mul(multiplier),
+            });
+        }
+
+        Some(BasicFlashParameters {
+            density_bits,
+            address_bytes: (dw1.bit_range(17..19) as u8).into(),
+            erase_4k_opcode,
+            erase_types,
+            fast_read,
+            page_size,
+            program_times,
+        })
+    }
+
+    /// Capacity of the device in bytes
+    pub fn capacity(&self) -> u64 {
+        self.density_bits / 8
+    }
+}
@@ -0,0 +1,672 @@
+//! Host side simulation of a MX25R chip, to run the drivers without the hardware.
+//!
+//! The [`Simulator`] implements both the blocking and async `SpiDevice` traits, so it can be given directly to the drivers.
+//! It decodes the single line instructions of the MX25R command set byte by byte, as the chip would see them on the bus,
+//! and executes the programs, erases and register writes once the chip select is released.
+//! The operations complete instantly unless a [`VirtualClock`] is given with [`Simulator::set_timing`],
+//! the wip bit then stays set for their duration and the suspend, reset and deep power down latencies are modelled.
+//! Each byte shifted on the bus advances the clock by [`SimTimings::byte_ns`] too, so that a driver polling without
+//! delays, e.g. with the [`Yield`](crate::poll::Yield) strategy, still sees the operations complete.
+//!
+//! The SFDP space answers the RDSFDP instruction with a JESD216B Basic Flash Parameter Table filled from the
+//! datasheet values of the part, read by `read_basic_flash_parameters` as on the hardware.
+//!
+//! Faults can be injected to test the power loss safety of the code above the drivers, see [`Simulator::set_faults`].
+//!
+//! The array is kept in a [`Storage`], in memory by default or in a file with [`FileStorage`].
+//! The registers and the secured OTP are only kept in memory, see [`Simulator::memory`] and [`Simulator::otp`] to inspect the content.
+
+use std::io;
+
+use embedded_hal::spi::{ErrorKind, ErrorType, Operation};
+
+use crate::{
+    command::Command,
+    part::{Part, MACRONIX_ID, MX25R_MEMORY_TYPE},
+    protection::BlockProtection,
+    register::*,
+    timing::Operation as FlashOperation,
+    BLOCK32_SIZE, BLOCK64_SIZE, PAGE_SIZE, SECTOR_SIZE, SECURE_OTP_SIZE,
+};
+
+pub mod clock;
+pub mod fault;
+mod sfdp;
+pub mod storage;
+
+use clock::{Busy, Timer};
+pub use clock::{SimTimings, VirtualClock};
+pub use fault::FaultConfig;
+use fault::{Faults, OperationFault};
+pub use storage::{FileStorage, Storage};
+
+/// Length of the command and address phases
+const ADDRESS_HEADER_LEN: usize = 4;
+
+/// Error returned by the simulated bus
+#[derive(Debug)]
+pub enum SimError {
+    /// The backing store failed to persist a program or erase
+    Storage(io::Error),
+    /// Injected bus error, the transaction didn't reach the chip
+    Bus,
+    /// The power was cut, see [`Simulator::power_cycle`]
+    PowerLoss,
+}
+
+impl embedded_hal::spi::Error for SimError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// A simulated MX25R chip, see the [module documentation](self)
+pub struct Simulator<S = Vec<u8>> {
+    part: Part,
+    storage: S,
+    otp: Vec<u8>,
+    sfdp: Vec<u8>,
+    status: StatusRegister,
+    config: ConfigurationRegister,
+    security: SecurityRegister,
+    /// Reads and programs target the secured OTP
+    in_otp: bool,
+    deep_power_down: bool,
+    /// The previous instruction was the reset enable
+    reset_enabled: bool,
+    /// Wrap around length of the reads, `None` if disabled
+    wrap_size: Option<u32>,
+    /// Bytes received since the chip select was asserted
+    frame: Vec<u8>,
+    faults: Option<Faults>,
+    timer: Option<Timer>,
+}
+
+impl Simulator {
+    /// A blank chip kept in memory, fully erased and unprotected
+    pub fn new(part: Part) -> Self {
+        Simulator::with_storage(part, vec![0xFF; part.capacity()])
+    }
+}
+
+impl<S: Storage> Simulator<S> {
+    /// A chip whose array is kept in `storage`, the registers start unprotected.
+    ///
+    /// # Panics
+    /// If the size of the storage doesn't match the capacity of the part
+    pub fn with_storage(part: Part, storage: S) -> Self {
+        assert_eq!(
+            storage.data().len(),
+            part.capacity(),
+            "the storage must match the capacity of the part"
+        );
+        Simulator {
+            part,
+            storage,
+            otp: vec![0xFF; SECURE_OTP_SIZE as usize],
+            sfdp: sfdp::space(part),
+            status: StatusRegister::from(0),
+            config: ConfigurationRegister::from([0; 2]),
+            security: SecurityRegister::from(0),
+            in_otp: false,
+            deep_power_down: false,
+            reset_enabled: false,
+            wrap_size: None,
+            frame: Vec::new(),
+            faults: None,
+            timer: None,
+        }
+    }
+
+    /// The simulated part
+    pub fn part(&self) -> Part {
+        self.part
+    }
+
+    /// Content of the main array
+    pub fn memory(&self) -> &[u8] {
+        self.storage.data()
+    }
+
+    /// The backing store of the main array, e.g. to preload an image in memory
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Give back the backing store of the main array
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    /// Content of the secured OTP
+    pub fn otp(&self) -> &[u8] {
+        &self.otp
+    }
+
+    /// Mutable content of the secured OTP, to preload the factory data
+    pub fn otp_mut(&mut self) -> &mut [u8] {
+        &mut self.otp
+    }
+
+    pub fn status(&self) -> StatusRegister {
+        StatusRegister {
+            wip_bit: self.busy().is_some(),
+            ..self.status
+        }
+    }
+
+    pub fn configuration(&self) -> ConfigurationRegister {
+        self.config
+    }
+
+    pub fn security(&self) -> SecurityRegister {
+        let suspended = self.timer.as_ref().and_then(Timer::suspended);
+        SecurityRegister {
+            program_suspended: suspended == Some(FlashOperation::Program),
+            erase_suspended: suspended
+                .is_some_and(|operation| operation != FlashOperation::Program),
+            ..self.security
+        }
+    }
+
+    /// If the chip is in deep power down, waiting for the release
+    pub fn is_deep_power_down(&self) -> bool {
+        self.deep_power_down
+    }
+
+    /// The range protected by the block protect bits
+    pub fn protected_range(&self) -> core::ops::Range<u32> {
+        BlockProtection {
+            protected_block: self.status.protected_block,
+            area: self.config.protected_section,
+        }
+        .range(self.part.capacity())
+    }
+
+    /// Inject the faults of `config`, or disable the injection with `None`. The power is restored
+    pub fn set_faults(&mut self, config: Option<FaultConfig>) {
+        self.faults = config.map(Faults::new);
+    }
+
+    /// Number of transactions since the faults were set, to choose when to cut the power
+    pub fn transactions(&self) -> u64 {
+        self.faults.as_ref().map_or(0, Faults::transactions)
+    }
+
+    /// If the chip is powered, the power is only lost through the injected faults
+    pub fn is_powered(&self) -> bool {
+        self.faults.as_ref().is_none_or(Faults::is_powered)
+    }
+
+    /// Power the chip off and on again, the volatile state is lost
+    pub fn power_cycle(&mut self) {
+        if let Some(faults) = &mut self.faults {
+            faults.power_cycle();
+        }
+        self.frame.clear();
+        self.deep_power_down = false;
+        self.reset_enabled = false;
+        self.reset();
+        if let Some(timer) = &mut self.timer {
+            timer.power_cycle();
+        }
+    }
+
+    /// Model the durations of the operations with `timings`, the time is given by `clock`.
+    /// The clock is only advanced by the delays, give a clone of it to the driver
+    pub fn set_timing(&mut self, clock: VirtualClock, timings: SimTimings) {
+        self.timer = Some(Timer::new(clock, timings));
+    }
+
+    /// The clock given to [`Self::set_timing`]
+    pub fn clock(&self) -> Option<&VirtualClock> {
+        self.timer.as_ref().map(Timer::clock)
+    }
+
+    /// What keeps the wip bit set
+    fn busy(&self) -> Option<Busy> {
+        self.timer.as_ref().and_then(Timer::busy)
+    }
+
+    /// The chip is recovering from the deep power down and ignores the bus
+    fn is_waking(&self) -> bool {
+        self.timer.as_ref().is_some_and(Timer::is_waking)
+    }
+
+    /// Set the wip bit for the duration of `operation`
+    fn start(&mut self, operation: FlashOperation) {
+        if let Some(timer) = &mut self.timer {
+            timer.start(operation);
+        }
+    }
+
+    /// Run the operations of a transaction, the chip select is released at the end
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SimError> {
+        if let Some(faults) = &mut self.faults {
+            faults.begin()?;
+        }
+        for operation in operations {
+            match operation {
+                Operation::Read(data) => {
+                    for byte in data.iter_mut() {
+                        *byte = self.exchange(Command::Dummy as u8);
+                    }
+                }
+                Operation::Write(data) => {
+                    for &byte in data.iter() {
+                        self.exchange(byte);
+                    }
+                }
+                Operation::Transfer(read, write) => {
+                    for i in 0..read.len().max(write.len()) {
+                        let byte = self.exchange(write.get(i).copied().unwrap_or(0xFF));
+                        if let Some(read) = read.get_mut(i) {
+                            *read = byte;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(data) => {
+                    for byte in data.iter_mut() {
+                        *byte = self.exchange(*byte);
+                    }
+                }
+                Operation::DelayNs(ns) => {
+                    if let Some(clock) = self.clock() {
+                        clock.advance_ns((*ns).into());
+                    }
+                }
+            }
+        }
+        self.deselect().map_err(SimError::Storage)?;
+        match &mut self.faults {
+            Some(faults) => faults.end(),
+            None => Ok(()),
+        }
+    }
+
+    /// Receive a byte and output the byte shifted out at the same time
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if let Some(timer) = &self.timer {
+            timer.shift();
+        }
+        self.frame.push(byte);
+        let index = self.frame.len() - 1;
+        let output = self.output(index);
+        if !self.is_read_data(index) {
+            return output;
+        }
+        match &mut self.faults {
+            Some(faults) => faults.read(output),
+            None => output,
+        }
+    }
+
+    /// If the byte at `index` of the frame is read from the array or the OTP
+    fn is_read_data(&self, index: usize) -> bool {
+        match Command::from_opcode(self.frame[0]) {
+            Some(Command::Read) => index >= ADDRESS_HEADER_LEN,
+            Some(Command::ReadF) => index > ADDRESS_HEADER_LEN,
+            _ => false,
+        }
+    }
+
+    /// Byte output at `index` of the frame, the bus is pulled high when the chip doesn't drive it
+    fn output(&self, index: usize) -> u8 {
+        let command = Command::from_opcode(self.frame[0]);
+        if index == 0
+            || self.is_waking()
+            || (self.deep_power_down && command != Some(Command::ReadElectronicId))
+        {
+            return 0xFF;
+        }
+        let Some(command) = command else {
+            return 0xFF;
+        };
+        if self.busy().is_some()
+            && !matches!(
+                command,
+                Command::ReadStatus | Command::ReadConfig | Command::ReadSecurityRegister
+            )
+        {
+            // Only the registers can be read while busy
+            return 0xFF;
+        }
+        match command {
+            Command::Read => self.read_output(index, ADDRESS_HEADER_LEN),
+            // The fast read has a dummy byte after the address
+            Command::ReadF => self.read_output(index, ADDRESS_HEADER_LEN + 1),
+            // The SFDP read has a dummy byte after the address
+            Command::ReadSfdp if index > ADDRESS_HEADER_LEN => {
+                let offset = self.frame_address() as usize + index - ADDRESS_HEADER_LEN - 1;
+                self.sfdp.get(offset).copied().unwrap_or(0xFF)
+            }
+            Command::ReadStatus => self.status().into(),
+            Command::ReadConfig => {
+                let config: [u8; 2] = self.config.into();
+                config[(index - 1) % 2]
+            }
+            Command::ReadSecurityRegister => self.security().into(),
+            Command::ReadIdentification => {
+                let id = [MACRONIX_ID.0, MX25R_MEMORY_TYPE.0, self.part.density().0];
+                id[(index - 1) % 3]
+            }
+            // 3 dummy bytes before the id
+            Command::ReadElectronicId if index >= ADDRESS_HEADER_LEN => self.part.density().0,
+            Command::ReadManufacturerId if index >= ADDRESS_HEADER_LEN => {
+                // The address selects which id comes first
+                let swap = self.frame[ADDRESS_HEADER_LEN - 1] & 1 == 1;
+                let manufacturer_first = (index - ADDRESS_HEADER_LEN).is_multiple_of(2);
+                if manufacturer_first != swap {
+                    MACRONIX_ID.0
+                } else {
+                    self.part.density().0
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    /// Data byte of a read at `index`, once the `header_len` bytes of the header are received
+    fn read_output(&self, index: usize, header_len: usize) -> u8 {
+        if index < header_len {
+            return 0xFF;
+        }
+        let addr = self.frame_address();
+        let offset = (index - header_len) as u32;
+        if self.in_otp {
+            return self.otp[((addr + offset) % SECURE_OTP_SIZE) as usize];
+        }
+        let addr = match self.wrap_size {
+            Some(wrap_size) => {
+                let window = addr & !(wrap_size - 1);
+                window + (addr - window + offset) % wrap_size
+            }
+            None => addr + offset,
+        };
+        self.storage.data()[self.array_index(addr)]
+    }
+
+    /// The address phase of the frame, the frame must be long enough
+    fn frame_address(&self) -> u32 {
+        u32::from_be_bytes([0, self.frame[1], self.frame[2], self.frame[3]])
+    }
+
+    /// The address bits above the capacity are ignored
+    fn array_index(&self, addr: u32) -> usize {
+        addr as usize % self.part.capacity()
+    }
+
+    /// Execute the instruction once the chip select is released
+    fn deselect(&mut self) -> io::Result<()> {
+        let frame = core::mem::take(&mut self.frame);
+        let Some(command) = frame
+            .first()
+            .and_then(|&opcode| Command::from_opcode(opcode))
+        else {
+            return Ok(());
+        };
+        if self.is_waking() {
+            return Ok(());
+        }
+        if self.deep_power_down {
+            // Only the release is accepted
+            if command == Command::ReadElectronicId {
+                self.deep_power_down = false;
+                if let Some(timer) = &mut self.timer {
+                    timer.wake();
+                }
+            }
+            return Ok(());
+        }
+        let program_erase = matches!(
+            command,
+            Command::ProgramPage
+                | Command::SectorErase
+                | Command::BlockErase32
+                | Command::BlockErase
+                | Command::ChipErase
+        );
+        if self.faults.as_ref().is_some_and(Faults::is_cutting) && !program_erase {
+            // The power is lost before the instruction is executed
+            return Ok(());
+        }
+        let reset_enabled = core::mem::take(&mut self.reset_enabled);
+        let accepted = match self.busy() {
+            // The chip only accepts the suspend and the reset while busy
+            Some(_) => matches!(
+                command,
+                Command::ProgramEraseSuspend | Command::ResetEnable | Command::ResetMemory
+            ),
+            // Nothing can be programmed or erased until the suspended operation is resumed
+            None if self.timer.as_ref().and_then(Timer::suspended).is_some() => {
+                !program_erase
+                    && !matches!(
+                        command,
+                        Command::WriteStatus | Command::WriteSecurityRegister
+                    )
+            }
+            None => true,
+        };
+        if !accepted {
+            return Ok(());
+        }
+        let addr = (frame.len() >= ADDRESS_HEADER_LEN)
+            .then(|| u32::from_be_bytes([0, frame[1], frame[2], frame[3]]));
+
+        match (command, addr) {
+            (Command::WriteEnable, _) => self.status.write_enable_latch = true,
+            (Command::WriteDisable, _) => self.status.write_enable_latch = false,
+            (Command::ProgramPage, Some(addr)) => {
+                return self.program(addr, &frame[ADDRESS_HEADER_LEN..])
+            }
+            (Command::SectorErase, Some(addr)) => return self.erase(addr, SECTOR_SIZE),
+            (Command::BlockErase32, Some(addr)) => return self.erase(addr, BLOCK32_SIZE),
+            (Command::BlockErase, Some(addr)) => return self.erase(addr, BLOCK64_SIZE),
+            (Command::ChipErase, _) => return self.erase(0, self.part.capacity() as u32),
+            (Command::WriteStatus, _) => self.write_status(&frame[1..]),
+            (Command::WriteSecurityRegister, _) => self.lock_down(),
+            (Command::EnterSecureOTP, _) => self.in_otp = true,
+            (Command::ExitSecureOTP, _) => self.in_otp = false,
+            (Command::DeepPowerDown, _) => self.deep_power_down = true,
+            (Command::SetBurstLength, _) => {
+                if let Some(&burst_length) = frame.get(1) {
+                    // Bit 4 disables the wrap around, the 2 low bits select 8 to 64 bytes
+                    self.wrap_size = (burst_length & 0x10 == 0).then(|| 8 << (burst_length & 0x03));
+                }
+            }
+            (Command::ResetEnable, _) => self.reset_enabled = true,
+            (Command::ResetMemory, _) if reset_enabled => {
+                self.reset();
+                if let Some(timer) = &mut self.timer {
+                    timer.reset();
+                }
+            }
+            (Command::ProgramEraseSuspend, _) => {
+                if let Some(timer) = &mut self.timer {
+                    timer.suspend();
+                }
+            }
+            (Command::ProgramEraseResume, _) => {
+                if let Some(timer) = &mut self.timer {
+                    timer.resume();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Program, erase and register writes are only accepted with the write enable latch set, which they clear
+    fn take_write_enable(&mut self) -> bool {
+        core::mem::replace(&mut self.status.write_enable_latch, false)
+    }
+
+    /// The failure flags report the last program or erase only
+    fn clear_failures(&mut self) {
+        self.security.program_failed = false;
+        self.security.erase_failed = false;
+    }
+
+    /// Page program, the data wraps around the page and only the last page size bytes are kept
+    fn program(&mut self, addr: u32, data: &[u8]) -> io::Result<()> {
+        if !self.take_write_enable() {
+            return Ok(());
+        }
+        self.clear_failures();
+        let addr = if self.in_otp {
+            addr % SECURE_OTP_SIZE
+        } else {
+            self.array_index(addr) as u32
+        };
+        let refused = if self.in_otp {
+            self.security.locked_down
+        } else {
+            self.protected_range().contains(&addr)
+        };
+        if refused {
+            self.security.program_failed = true;
+            return Ok(());
+        }
+        self.start(FlashOperation::Program);
+
+        let page = (addr & !(PAGE_SIZE - 1)) as usize;
+        let page_end = page + PAGE_SIZE as usize;
+        let mut content = [0; PAGE_SIZE as usize];
+        let source = if self.in_otp {
+            &self.otp[..]
+        } else {
+            self.storage.data()
+        };
+        content.copy_from_slice(&source[page..page_end]);
+        // The bytes overwritten in the page buffer are never programmed
+        let skipped = data.len().saturating_sub(content.len());
+        let data = &data[skipped..];
+        let start = addr as usize - page + skipped;
+        let fault = self
+            .faults
+            .as_mut()
+            .and_then(|faults| faults.program(data.len()));
+        let done = fault.map_or(data.len(), |fault| fault.done);
+        for (i, &byte) in data.iter().enumerate().take(done) {
+            // Programming only clears bits
+            content[(start + i) % content.len()] &= byte;
+        }
+        if let Some(fault) = fault {
+            self.interrupted(fault, |security| security.program_failed = true);
+            if let Some(&byte) = data.get(done) {
+                // Only some of the bits of the next byte were cleared
+                content[(start + done) % content.len()] &= byte | fault.noise;
+            }
+        }
+
+        if self.in_otp {
+            self.otp[page..page_end].copy_from_slice(&content);
+            return Ok(());
+        }
+        self.storage.write(page, &content)
+    }
+
+    /// Erase the `size` bytes aligned block containing `addr`, refused if any byte is protected
+    fn erase(&mut self, addr: u32, size: u32) -> io::Result<()> {
+        if !self.take_write_enable() {
+            return Ok(());
+        }
+        self.clear_failures();
+        let start = self.array_index(addr) as u32 & !(size - 1);
+        let end = start + size;
+        let protected = self.protected_range();
+        if self.in_otp || (protected.start < end && start < protected.end) {
+            self.security.erase_failed = true;
+            return Ok(());
+        }
+        self.start(match size {
+            SECTOR_SIZE => FlashOperation::SectorErase,
+            BLOCK32_SIZE => FlashOperation::Block32Erase,
+            BLOCK64_SIZE => FlashOperation::Block64Erase,
+            _ => FlashOperation::ChipErase,
+        });
+        let Some(fault) = self
+            .faults
+            .as_mut()
+            .and_then(|faults| faults.erase(size as usize))
+        else {
+            return self
+                .storage
+                .write(start as usize, &vec![0xFF; size as usize]);
+        };
+        self.interrupted(fault, |security| security.erase_failed = true);
+        let mut content = vec![0xFF; fault.done];
+        if let Some(&byte) = self.storage.data().get(start as usize + fault.done) {
+            if fault.done < size as usize {
+                // Only some of the bits of the next byte were set
+                content.push(byte | fault.noise);
+            }
+        }
+        self.storage.write(start as usize, &content)
+    }
+
+    /// Report an interrupted program or erase, unless the power is lost
+    fn interrupted(&mut self, fault: OperationFault, report: impl FnOnce(&mut SecurityRegister)) {
+        if !fault.power_loss {
+            report(&mut self.security);
+        }
+    }
+
+    /// Write the status register, optionally followed by the configuration register
+    fn write_status(&mut self, bytes: &[u8]) {
+        let Some(&status) = bytes.first() else {
+            return;
+        };
+        if !self.take_write_enable() {
+            return;
+        }
+        self.start(FlashOperation::WriteRegister);
+        let status = StatusRegister::from(status);
+        self.status.write_protect_disable = status.write_protect_disable;
+        self.status.quad_enable = status.quad_enable;
+        self.status.protected_block = status.protected_block;
+
+        if let Some(&first) = bytes.get(1) {
+            let current: [u8; 2] = self.config.into();
+            let config =
+                ConfigurationRegister::from([first, bytes.get(2).copied().unwrap_or(current[1])]);
+            self.config.dummmy_cycle = config.dummmy_cycle;
+            self.config.power_mode = config.power_mode;
+            // The top/bottom bit is one time programmable
+            if config.protected_section == ProtectedArea::Bottom {
+                self.config.protected_section = ProtectedArea::Bottom;
+            }
+        }
+    }
+
+    /// Set the lock down bit of the security register, the secured OTP can't be programmed anymore
+    fn lock_down(&mut self) {
+        if self.take_write_enable() {
+            self.start(FlashOperation::WriteRegister);
+            self.security.locked_down = true;
+        }
+    }
+
+    /// The reset clears the volatile state, the registers are non volatile
+    fn reset(&mut self) {
+        self.status.write_enable_latch = false;
+        self.in_otp = false;
+        self.wrap_size = None;
+    }
+}
+
+impl<S> ErrorType for Simulator<S> {
+    type Error = SimError;
+}
+
+impl<S: Storage> embedded_hal::spi::SpiDevice for Simulator<S> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SimError> {
+        Simulator::transaction(self, operations)
+    }
+}
+
+impl<S: Storage> embedded_hal_async::spi::SpiDevice for Simulator<S> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SimError> {
+        Simulator::transaction(self, operations)
+    }
+}
@@ -0,0 +1,215 @@
+//! Simulated time, enabled with [`Simulator::set_timing`](super::Simulator::set_timing).
+//!
+//! The [`VirtualClock`] only moves when it is asked to wait, it is shared between the simulator and the delay given to the driver.
+//! The timeouts and polling strategies then run instantly and deterministically.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::{
+    part::Part,
+    timing::{Operation, Timings, ERASE_SUSPEND_LATENCY_US},
+    DEEP_POWER_DOWN_RECOVERY_US,
+};
+
+/// Time for the chip to recover from a reset interrupting an erase (tREADY2), the worst case
+const RESET_RECOVERY_US: u32 = 12_000;
+
+/// Time to shift a byte with an 8MHz SPI clock
+const BYTE_NS: u32 = 1_000;
+
+/// A clock advanced by the delays, the clones share the same time
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now_ns: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    /// A clock starting at 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time elapsed since the start in nanoseconds
+    pub fn now_ns(&self) -> u64 {
+        self.now_ns.load(Ordering::Relaxed)
+    }
+
+    /// Time elapsed since the start in microseconds
+    pub fn now_us(&self) -> u64 {
+        self.now_ns() / 1000
+    }
+
+    pub fn advance_ns(&self, ns: u64) {
+        self.now_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    pub fn advance_us(&self, us: u64) {
+        self.advance_ns(us * 1000);
+    }
+}
+
+impl embedded_hal::delay::DelayNs for VirtualClock {
+    fn delay_ns(&mut self, ns: u32) {
+        self.advance_ns(ns.into());
+    }
+}
+
+impl embedded_hal_async::delay::DelayNs for VirtualClock {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.advance_ns(ns.into());
+    }
+}
+
+/// Durations of the simulated chip in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimTimings {
+    /// Durations of the operations setting the wip bit
+    pub operations: Timings,
+    /// Time to suspend a program or erase (tESL)
+    pub suspend_latency_us: u32,
+    /// Time to recover from a reset, the chip only answers the register reads meanwhile
+    pub reset_recovery_us: u32,
+    /// Time to recover from the release of the deep power down (tRDP), the chip ignores the bus meanwhile
+    pub wake_us: u32,
+    /// Time to shift a byte on the bus in nanoseconds, so that the time passes while polling without delays
+    pub byte_ns: u32,
+}
+
+impl SimTimings {
+    /// The limits of the datasheet, the slowest chip the drivers must handle
+    pub const fn max(part: Part) -> Self {
+        SimTimings {
+            operations: part.max_timings(),
+            suspend_latency_us: ERASE_SUSPEND_LATENCY_US,
+            reset_recovery_us: RESET_RECOVERY_US,
+            wake_us: DEEP_POWER_DOWN_RECOVERY_US,
+            byte_ns: BYTE_NS,
+        }
+    }
+
+    /// The typical durations of the datasheet, the latencies are kept at their limits
+    pub const fn typical(part: Part) -> Self {
+        SimTimings {
+            operations: part.typical_timings(),
+            ..Self::max(part)
+        }
+    }
+}
+
+/// What keeps the wip bit set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Busy {
+    Operation(Operation),
+    Suspending,
+    Resetting,
+}
+
+/// State of the simulated time
+pub(crate) struct Timer {
+    clock: VirtualClock,
+    timings: SimTimings,
+    /// The wip bit is set until the deadline, in nanoseconds
+    busy: Option<(Busy, u64)>,
+    /// Suspended operation and its remaining duration in nanoseconds
+    suspended: Option<(Operation, u64)>,
+    /// End of the deep power down recovery in nanoseconds
+    awake_at: u64,
+}
+
+impl Timer {
+    pub(crate) fn new(clock: VirtualClock, timings: SimTimings) -> Self {
+        Timer {
+            clock,
+            timings,
+            busy: None,
+            suspended: None,
+            awake_at: 0,
+        }
+    }
+
+    pub(crate) fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// A byte is shifted on the bus
+    pub(crate) fn shift(&self) {
+        self.clock.advance_ns(self.timings.byte_ns.into());
+    }
+
+    fn deadline(&self, duration_us: u32) -> u64 {
+        self.clock.now_ns() + u64::from(duration_us) * 1000
+    }
+
+    /// What keeps the chip busy right now
+    pub(crate) fn busy(&self) -> Option<Busy> {
+        self.busy
+            .filter(|&(_, deadline)| self.clock.now_ns() < deadline)
+            .map(|(busy, _)| busy)
+    }
+
+    /// The operation suspended, once the suspend latency elapsed
+    pub(crate) fn suspended(&self) -> Option<Operation> {
+        self.suspended
+            .filter(|_| self.busy().is_none())
+            .map(|(operation, _)| operation)
+    }
+
+    /// The chip is recovering from the deep power down
+    pub(crate) fn is_waking(&self) -> bool {
+        self.clock.now_ns() < self.awake_at
+    }
+
+    pub(crate) fn start(&mut self, operation: Operation) {
+        let duration_us = self.timings.operations.duration_us(operation);
+        self.busy = Some((Busy::Operation(operation), self.deadline(duration_us)));
+    }
+
+    /// Suspend the ongoing program or erase, the chip erase and the register writes can't be suspended
+    pub(crate) fn suspend(&mut self) {
+        let Some(Busy::Operation(operation)) = self.busy() else {
+            return;
+        };
+        if matches!(operation, Operation::ChipErase | Operation::WriteRegister) {
+            return;
+        }
+        let remaining = self.busy.map_or(0, |(_, deadline)| deadline) - self.clock.now_ns();
+        self.suspended = Some((operation, remaining));
+        self.busy = Some((
+            Busy::Suspending,
+            self.deadline(self.timings.suspend_latency_us),
+        ));
+    }
+
+    pub(crate) fn resume(&mut self) {
+        if self.suspended().is_none() {
+            return;
+        }
+        if let Some((operation, remaining)) = self.suspended.take() {
+            self.busy = Some((Busy::Operation(operation), self.clock.now_ns() + remaining));
+        }
+    }
+
+    /// The reset aborts the ongoing or suspended operation
+    pub(crate) fn reset(&mut self) {
+        self.suspended = None;
+        self.busy = Some((
+            Busy::Resetting,
+            self.deadline(self.timings.reset_recovery_us),
+        ));
+    }
+
+    /// Release from the deep power down
+    pub(crate) fn wake(&mut self) {
+        self.awake_at = self.deadline(self.timings.wake_us);
+    }
+
+    /// The power loss aborts everything
+    pub(crate) fn power_cycle(&mut self) {
+        self.busy = None;
+        self.suspended = None;
+        self.awake_at = 0;
+    }
+}
@@ -0,0 +1,167 @@
+//! Reproducible fault injection, enabled with [`Simulator::set_faults`](super::Simulator::set_faults).
+//!
+//! Every fault is drawn from a generator seeded by [`FaultConfig::seed`], so a failing run can be replayed with the same seed.
+
+use super::SimError;
+
+/// The faults injected by the simulator, all disabled by default
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    /// Seed of the generator drawing the faults
+    pub seed: u64,
+    /// Cut the power during the `n`th transaction, counted from 1.
+    /// A program or erase it sends is left partially done, any other instruction is lost
+    pub power_loss_after: Option<u64>,
+    /// Probability that a program or erase is interrupted by a power loss, leaving it partially done
+    pub power_loss_probability: f64,
+    /// Probability that a program is left partially done and reported in the security register
+    pub program_failure_probability: f64,
+    /// Probability that an erase is left partially done and reported in the security register
+    pub erase_failure_probability: f64,
+    /// Probability that a bit of each byte read from the array is flipped, the array itself is left untouched
+    pub read_bit_flip_probability: f64,
+    /// Probability that a transaction fails with [`SimError::Bus`] without reaching the chip
+    pub bus_error_probability: f64,
+}
+
+/// Xorshift generator, good enough to draw faults and free of dependencies
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Splitmix the seed so that close seeds give unrelated sequences, the state must not be 0
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Rng((z ^ (z >> 31)) | 1)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A value in `0..=max`
+    pub(crate) fn up_to(&mut self, max: usize) -> usize {
+        (self.next_u64() % (max as u64 + 1)) as usize
+    }
+
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        // 53 random bits give a uniform value in 0..1
+        probability > 0.0 && ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// How far a faulty program or erase got
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OperationFault {
+    /// Number of bytes fully programmed or erased
+    pub(crate) done: usize,
+    /// Random bits of the next byte, only partially programmed or erased
+    pub(crate) noise: u8,
+    /// The power is lost, otherwise the failure is reported in the security register
+    pub(crate) power_loss: bool,
+}
+
+/// State of the fault injection
+pub(crate) struct Faults {
+    config: FaultConfig,
+    rng: Rng,
+    transactions: u64,
+    powered: bool,
+    /// The power is cut at the end of the current transaction
+    cutting: bool,
+}
+
+impl Faults {
+    pub(crate) fn new(config: FaultConfig) -> Self {
+        Faults {
+            rng: Rng::new(config.seed),
+            config,
+            transactions: 0,
+            powered: true,
+            cutting: false,
+        }
+    }
+
+    /// Number of transactions started since the faults were set
+    pub(crate) fn transactions(&self) -> u64 {
+        self.transactions
+    }
+
+    pub(crate) fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// The power is cut during the current transaction
+    pub(crate) fn is_cutting(&self) -> bool {
+        self.cutting
+    }
+
+    pub(crate) fn power_cycle(&mut self) {
+        self.powered = true;
+        self.cutting = false;
+    }
+
+    /// Called before a transaction reaches the chip
+    pub(crate) fn begin(&mut self) -> Result<(), SimError> {
+        if !self.powered {
+            return Err(SimError::PowerLoss);
+        }
+        self.transactions += 1;
+        if self.config.power_loss_after == Some(self.transactions) {
+            self.cutting = true;
+        } else if self.rng.chance(self.config.bus_error_probability) {
+            return Err(SimError::Bus);
+        }
+        Ok(())
+    }
+
+    /// Called once the chip select is released
+    pub(crate) fn end(&mut self) -> Result<(), SimError> {
+        if self.cutting {
+            self.cutting = false;
+            self.powered = false;
+            return Err(SimError::PowerLoss);
+        }
+        Ok(())
+    }
+
+    /// Draw the fault of a program of `len` bytes
+    pub(crate) fn program(&mut self, len: usize) -> Option<OperationFault> {
+        self.operation(len, self.config.program_failure_probability)
+    }
+
+    /// Draw the fault of an erase of `len` bytes
+    pub(crate) fn erase(&mut self, len: usize) -> Option<OperationFault> {
+        self.operation(len, self.config.erase_failure_probability)
+    }
+
+    fn operation(&mut self, len: usize, failure_probability: f64) -> Option<OperationFault> {
+        if !self.cutting && self.rng.chance(self.config.power_loss_probability) {
+            self.cutting = true;
+        }
+        let power_loss = self.cutting;
+        if !power_loss && !self.rng.chance(failure_probability) {
+            return None;
+        }
+        Some(OperationFault {
+            done: self.rng.up_to(len),
+            noise: self.rng.next_u64() as u8,
+            power_loss,
+        })
+    }
+
+    /// A byte read from the array, possibly with a flipped bit
+    pub(crate) fn read(&mut self, byte: u8) -> u8 {
+        if !self.rng.chance(self.config.read_bit_flip_probability) {
+            return byte;
+        }
+        byte ^ (1 << self.rng.up_to(7))
+    }
+}
@@ -0,0 +1,110 @@
+//! SFDP space of the simulated chip, with a JESD216B Basic Flash Parameter Table filled from the datasheet values.
+
+use crate::{
+    command::Command,
+    part::Part,
+    sfdp::{
+        BASIC_FLASH_PARAMETER_ID, BASIC_FLASH_PARAMETER_MAX_DWORDS, HEADER_SIZE, SFDP_SIGNATURE,
+    },
+};
+
+/// JESD216B
+const MINOR_REVISION: u8 = 6;
+const MAJOR_REVISION: u8 = 1;
+
+/// The table follows the SFDP header and its only parameter header
+const BASIC_FLASH_PARAMETERS_POINTER: u32 = 2 * HEADER_SIZE as u32;
+
+/// Encode `value` as a 5 bits count of the smallest of `units` that fits, returns the count minus one and the unit index
+fn duration(value: u32, units: &[u32]) -> (u32, u32) {
+    for (index, &unit) in units.iter().enumerate() {
+        let count = value.div_ceil(unit).max(1);
+        if count <= 32 {
+            return (count - 1, index as u32);
+        }
+    }
+    (31, units.len() as u32 - 1)
+}
+
+/// Encode the ratio between the maximum and typical durations, the maximum is `2 * (n + 1)` times the typical
+fn multiplier(max: u32, typical: u32) -> u32 {
+    (max.div_ceil(typical).div_ceil(2).max(1) - 1).min(0xF)
+}
+
+/// The SFDP space of `part`, the addresses past the end read as erased
+pub(crate) fn space(part: Part) -> Vec<u8> {
+    let typical = part.typical_timings();
+    let max = part.max_timings();
+
+    let erase_types = [
+        (Command::SectorErase, 12, typical.sector_erase_us),
+        (Command::BlockErase32, 15, typical.block32_erase_us),
+        (Command::BlockErase, 16, typical.block64_erase_us),
+    ];
+    // The 4th erase type is absent, its size is 0
+    let mut erase_type = [0; 4];
+    let mut erase_times = multiplier(max.sector_erase_us, typical.sector_erase_us);
+    for (i, &(command, size, typical_us)) in erase_types.iter().enumerate() {
+        erase_type[i] = (command as u32) << 8 | size;
+        let (count, unit) = duration(typical_us / 1000, &[1, 16, 128, 1000]);
+        erase_times |= (count | unit << 5) << (4 + 7 * i);
+    }
+
+    let (page_count, page_unit) = duration(typical.page_program_us, &[8, 64]);
+    let (chip_count, chip_unit) = duration(typical.chip_erase_us / 1000, &[16, 256, 4_000, 64_000]);
+    let program_times = multiplier(max.page_program_us, typical.page_program_us)
+        // 256 bytes pages
+        | 8 << 4
+        | page_count << 8
+        | page_unit << 13
+        // 32us for the first byte, 1us for the next ones
+        | 3 << 14
+        | 1 << 18
+        | chip_count << 24
+        | chip_unit << 29;
+
+    let dwords: [u32; BASIC_FLASH_PARAMETER_MAX_DWORDS] = [
+        // Uniform 4kB erase, the 1-1-2, 1-2-2, 1-4-4 and 1-1-4 reads, 3 bytes addresses
+        0xFFF1_00E5 | (Command::SectorErase as u32) << 8,
+        (part.capacity() as u32 * 8) - 1,
+        // 1-4-4 with 4 dummy and 2 mode clocks, 1-1-4 with 8 dummy clocks
+        (Command::ReadQ as u32) << 24 | 0x08 << 16 | (Command::Read4 as u32) << 8 | 0x44,
+        // 1-1-2 with 8 dummy clocks, 1-2-2 with 4 dummy clocks
+        (Command::Read2 as u32) << 24 | 0x04 << 16 | (Command::ReadD as u32) << 8 | 0x08,
+        // Neither 2-2-2 nor 4-4-4
+        0xFFFF_FFEE,
+        0x0000_FFFF,
+        0x0000_FFFF,
+        erase_type[0] | erase_type[1] << 16,
+        erase_type[2] | erase_type[3] << 16,
+        erase_times,
+        program_times,
+        // Suspend, resume and power down parameters, not decoded by the drivers
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+    ];
+
+    let mut space = Vec::with_capacity(BASIC_FLASH_PARAMETERS_POINTER as usize + dwords.len() * 4);
+    space.extend(SFDP_SIGNATURE.to_le_bytes());
+    // A single parameter header, the field is zero based
+    space.extend([MINOR_REVISION, MAJOR_REVISION, 0, 0xFF]);
+    let [id_lsb, id_msb] = BASIC_FLASH_PARAMETER_ID.to_le_bytes();
+    let [pointer_0, pointer_1, pointer_2, _] = BASIC_FLASH_PARAMETERS_POINTER.to_le_bytes();
+    space.extend([
+        id_lsb,
+        MINOR_REVISION,
+        MAJOR_REVISION,
+        dwords.len() as u8,
+        pointer_0,
+        pointer_1,
+        pointer_2,
+        id_msb,
+    ]);
+    for dword in dwords {
+        space.extend(dword.to_le_bytes());
+    }
+    space
+}
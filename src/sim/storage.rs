@@ -0,0 +1,129 @@
+//! Backing stores of the simulated array.
+//!
+//! The simulator applies the NOR rules itself, a store only keeps the bytes it is given.
+//! `Vec<u8>` keeps the array in memory, [`FileStorage`] persists it to a file.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Backing store of the simulated array
+pub trait Storage {
+    /// The content of the array
+    fn data(&self) -> &[u8];
+
+    /// Replace the bytes at `offset` with `bytes`, the range is within the array
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()>;
+}
+
+impl Storage for Vec<u8> {
+    fn data(&self) -> &[u8] {
+        self
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        self[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// An array persisted to a file, as a raw image that can be inspected with the usual tools.
+///
+/// The image is kept in memory and every program or erase is written through to the file.
+/// It can start from a read only base image, copied to the file on the first write.
+pub struct FileStorage {
+    path: PathBuf,
+    data: Vec<u8>,
+    /// `None` until the first write if the file didn't exist
+    file: Option<File>,
+}
+
+impl FileStorage {
+    /// Open the image at `path`, a missing file starts erased and is created on the first write.
+    /// Returns [`io::ErrorKind::InvalidData`] if the existing image isn't `capacity` bytes long
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        Self::open_with(path, capacity, || Ok(vec![0xFF; capacity]))
+    }
+
+    /// Open the image at `path`, a missing file starts from a copy of the `base` image, padded with erased bytes.
+    /// The base is never modified, the copy is made on the first write. An existing image is reopened as it is,
+    /// the base is then not read at all.
+    /// Returns [`io::ErrorKind::InvalidData`] if the existing image isn't `capacity` bytes long, or if the image
+    /// is missing and the base is longer than `capacity` bytes
+    pub fn open_with_base(
+        path: impl AsRef<Path>,
+        base: impl AsRef<Path>,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        Self::open_with(path, capacity, || {
+            let mut data = std::fs::read(base)?;
+            if data.len() > capacity {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "base image larger than the chip",
+                ));
+            }
+            data.resize(capacity, 0xFF);
+            Ok(data)
+        })
+    }
+
+    /// Read the image at `path`, or call `blank` for the initial content if it doesn't exist
+    fn open_with(
+        path: impl AsRef<Path>,
+        capacity: usize,
+        blank: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (data, file) = match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(mut file) => {
+                let mut data = Vec::with_capacity(capacity);
+                file.read_to_end(&mut data)?;
+                if data.len() != capacity {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "image size doesn't match the chip",
+                    ));
+                }
+                (data, Some(file))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (blank()?, None),
+            Err(e) => return Err(e),
+        };
+        Ok(FileStorage { path, data, file })
+    }
+
+    /// Path of the image
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create the image with the current content if it doesn't exist yet
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&self.path)?;
+            file.write_all(&self.data)?;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Storage for FileStorage {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let file = self.file()?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(bytes)
+    }
+}
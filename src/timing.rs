@@ -0,0 +1,98 @@
+//! Typical and maximum durations of the operations setting the write in progress bit.
+//!
+//! The durations are taken from the datasheets, the limits are rounded up and are used by the drivers to time out while waiting for the chip.
+
+use crate::part::Part;
+
+/// An operation keeping the chip busy until it completes
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Page program (PP or 4PP)
+    Program,
+    /// 4kB sector erase (SE)
+    SectorErase,
+    /// 32kB block erase (BE32K)
+    Block32Erase,
+    /// 64kB block erase (BE)
+    Block64Erase,
+    /// Chip erase (CE)
+    ChipErase,
+    /// Write of the status, configuration or security register (WRSR or WRSCUR)
+    WriteRegister,
+}
+
+/// Durations of the operations in microseconds
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings {
+    pub page_program_us: u32,
+    pub sector_erase_us: u32,
+    pub block32_erase_us: u32,
+    pub block64_erase_us: u32,
+    pub chip_erase_us: u32,
+    pub write_register_us: u32,
+}
+
+impl Timings {
+    /// Duration of `operation` in microseconds
+    pub const fn duration_us(&self, operation: Operation) -> u32 {
+        match operation {
+            Operation::Program => self.page_program_us,
+            Operation::SectorErase => self.sector_erase_us,
+            Operation::Block32Erase => self.block32_erase_us,
+            Operation::Block64Erase => self.block64_erase_us,
+            Operation::ChipErase => self.chip_erase_us,
+            Operation::WriteRegister => self.write_register_us,
+        }
+    }
+}
+
+/// Chip erase limit of the largest part, the smaller parts scale it down with their density
+const MX25R6435F_CHIP_ERASE_MAX_US: u32 = 240_000_000;
+
+/// Typical chip erase duration of the largest part
+const MX25R6435F_CHIP_ERASE_TYPICAL_US: u32 = 50_000_000;
+
+/// Scale the chip erase duration of the largest part to `part`, the whole chip takes at least as long as one of its blocks
+const fn chip_erase_us(part: Part, largest_us: u32, block64_us: u32) -> u32 {
+    let chip_erase_us = largest_us >> (Part::MX25R6435F as u32 - part as u32);
+    if chip_erase_us > block64_us {
+        chip_erase_us
+    } else {
+        block64_us
+    }
+}
+
+impl Part {
+    /// Maximum durations of the operations, the same for both power modes
+    pub const fn max_timings(&self) -> Timings {
+        const BLOCK64_ERASE_US: u32 = 3_500_000;
+
+        Timings {
+            page_program_us: 10_000,
+            sector_erase_us: 240_000,
+            block32_erase_us: 3_000_000,
+            block64_erase_us: BLOCK64_ERASE_US,
+            chip_erase_us: chip_erase_us(*self, MX25R6435F_CHIP_ERASE_MAX_US, BLOCK64_ERASE_US),
+            write_register_us: 40_000,
+        }
+    }
+
+    /// Typical durations of the operations in the high performance mode
+    pub const fn typical_timings(&self) -> Timings {
+        const BLOCK64_ERASE_US: u32 = 400_000;
+
+        Timings {
+            page_program_us: 850,
+            sector_erase_us: 40_000,
+            block32_erase_us: 200_000,
+            block64_erase_us: BLOCK64_ERASE_US,
+            chip_erase_us: chip_erase_us(*self, MX25R6435F_CHIP_ERASE_TYPICAL_US, BLOCK64_ERASE_US),
+            write_register_us: 10_000,
+        }
+    }
+}
+
+/// Maximum time for the chip to suspend an erase once requested (tESL), in microseconds
+pub const ERASE_SUSPEND_LATENCY_US: u32 = 60;
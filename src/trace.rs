@@ -0,0 +1,299 @@
+//! Decoding of the bus traffic, to debug the driver or the wiring without a logic analyzer.
+//!
+//! [`Trace`] wraps a blocking or async [`SpiDevice`](embedded_hal::spi::SpiDevice) and decodes each transaction into an
+//! [`Event`] given to a [`TraceSink`]: a closure, `DefmtSink` with the `defmt` feature or `LogSink` with the `log` feature.
+//! Only the header and the first bytes of the data phase are looked at, the data itself is neither copied nor logged.
+
+use core::fmt;
+
+use embedded_hal::spi::{ErrorType, Operation};
+
+use crate::{
+    command::Command,
+    register::{ConfigurationRegister, SecurityRegister, StatusRegister},
+};
+
+/// Longest header: the opcode, the address and the dummy byte of the fast reads
+const HEADER_MAX_LEN: usize = 5;
+
+/// Bytes of the data phase kept to decode the registers, the status and configuration written by WRSR
+const DATA_KEPT: usize = 3;
+
+/// A decoded transaction
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// First byte written in the transaction
+    pub opcode: u8,
+    /// Mnemonic of the instruction in the datasheet, `None` if the opcode isn't sent by the drivers
+    pub name: Option<&'static str>,
+    pub address: Option<u32>,
+    pub dummy_bytes: u8,
+    /// Number of bytes written after the header
+    pub written: usize,
+    /// Number of bytes read after the header
+    pub read: usize,
+    /// Status read by RDSR or written by WRSR
+    pub status: Option<StatusRegister>,
+    /// Configuration read by RDCR or written by WRSR
+    pub configuration: Option<ConfigurationRegister>,
+    /// Security register read by RDSCUR
+    pub security: Option<SecurityRegister>,
+    /// The transaction returned an error, the data read isn't decoded
+    pub failed: bool,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(name) => f.write_str(name)?,
+            None => write!(f, "{:#04x}", self.opcode)?,
+        }
+        if let Some(address) = self.address {
+            write!(f, " at {:#08x}", address)?;
+        }
+        if self.dummy_bytes > 0 {
+            write!(f, ", {} dummy", self.dummy_bytes)?;
+        }
+        if self.written > 0 {
+            write!(f, ", {} written", self.written)?;
+        }
+        if self.read > 0 {
+            write!(f, ", {} read", self.read)?;
+        }
+        if let Some(status) = self.status {
+            write!(f, ", status {}", status)?;
+        }
+        if let Some(configuration) = self.configuration {
+            write!(f, ", configuration {}", configuration)?;
+        }
+        if let Some(security) = self.security {
+            write!(f, ", security {}", security)?;
+        }
+        if self.failed {
+            f.write_str(", failed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives the decoded transactions
+pub trait TraceSink {
+    fn event(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event)> TraceSink for F {
+    fn event(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Log the events with `defmt::trace!`
+#[cfg(feature = "defmt")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DefmtSink;
+
+#[cfg(feature = "defmt")]
+impl TraceSink for DefmtSink {
+    fn event(&mut self, event: &Event) {
+        defmt::trace!("{}", event);
+    }
+}
+
+/// Log the events with `log::trace!`
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LogSink;
+
+#[cfg(feature = "log")]
+impl TraceSink for LogSink {
+    fn event(&mut self, event: &Event) {
+        log::trace!("{}", event);
+    }
+}
+
+/// A bus forwarding the transactions to `spi` and their decoding to `sink`
+pub struct Trace<SPI, S> {
+    spi: SPI,
+    sink: S,
+}
+
+impl<SPI, S: TraceSink> Trace<SPI, S> {
+    pub fn new(spi: SPI, sink: S) -> Self {
+        Trace { spi, sink }
+    }
+
+    /// Release the bus and the sink
+    pub fn release(self) -> (SPI, S) {
+        (self.spi, self.sink)
+    }
+}
+
+impl<SPI: ErrorType, S> ErrorType for Trace<SPI, S> {
+    type Error = SPI::Error;
+}
+
+impl<SPI, S> embedded_hal::spi::SpiDevice for Trace<SPI, S>
+where
+    SPI: embedded_hal::spi::SpiDevice,
+    S: TraceSink,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        // The written bytes of the in place transfers are replaced by the read ones
+        let header = Header::new(operations);
+        let res = self.spi.transaction(operations);
+        self.sink.event(&header.event(operations, res.is_err()));
+        res
+    }
+}
+
+impl<SPI, S> embedded_hal_async::spi::SpiDevice for Trace<SPI, S>
+where
+    SPI: embedded_hal_async::spi::SpiDevice,
+    S: TraceSink,
+{
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let header = Header::new(operations);
+        let res = self.spi.transaction(operations).await;
+        self.sink.event(&header.event(operations, res.is_err()));
+        res
+    }
+}
+
+/// Direction of the bytes of a transaction
+#[derive(Clone, Copy)]
+enum Side {
+    Written,
+    Read,
+}
+
+impl Side {
+    fn bytes<'a>(self, operation: &'a Operation<'_, u8>) -> Option<&'a [u8]> {
+        match (self, operation) {
+            (Side::Written, Operation::Write(bytes))
+            | (Side::Written, Operation::Transfer(_, bytes)) => Some(bytes),
+            (Side::Read, Operation::Read(bytes))
+            | (Side::Read, Operation::Transfer(bytes, _))
+            | (_, Operation::TransferInPlace(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+/// Number of bytes clocked by the operation
+fn clocked(operation: &Operation<'_, u8>) -> usize {
+    match operation {
+        Operation::Read(bytes) | Operation::TransferInPlace(bytes) => bytes.len(),
+        Operation::Write(bytes) => bytes.len(),
+        Operation::Transfer(read, write) => read.len().max(write.len()),
+        Operation::DelayNs(_) => 0,
+    }
+}
+
+/// The bytes of one side of a transaction past the header
+#[derive(Default)]
+struct Data {
+    len: usize,
+    first: [u8; DATA_KEPT],
+}
+
+/// Copy the header bytes of `side` in `header` and count the bytes past it
+fn scan(operations: &[Operation<'_, u8>], side: Side, header: &mut [u8]) -> Data {
+    let mut data = Data::default();
+    let mut position = 0;
+    for operation in operations {
+        if let Some(bytes) = side.bytes(operation) {
+            for (index, &byte) in (position..).zip(bytes) {
+                if let Some(dst) = header.get_mut(index) {
+                    *dst = byte;
+                } else if let Some(dst) = data.first.get_mut(index - header.len()) {
+                    *dst = byte;
+                } else {
+                    break;
+                }
+            }
+            data.len += (position + bytes.len()).saturating_sub(header.len().max(position));
+        }
+        position += clocked(operation);
+    }
+    data
+}
+
+/// What is known of a transaction before it runs
+struct Header {
+    command: Option<Command>,
+    opcode: u8,
+    address: Option<u32>,
+    dummy_bytes: u8,
+    len: usize,
+    written: Data,
+}
+
+impl Header {
+    fn new(operations: &[Operation<'_, u8>]) -> Self {
+        // A read without a write first clocks dummy bytes
+        let mut opcode = [Command::Dummy as u8];
+        scan(operations, Side::Written, &mut opcode);
+        let [opcode] = opcode;
+        let command = Command::from_opcode(opcode);
+        let (has_address, dummy_bytes) = command.map_or((false, 0), Command::header);
+
+        let len = 1 + if has_address { 3 } else { 0 } + dummy_bytes as usize;
+        let mut header = [0; HEADER_MAX_LEN];
+        let written = scan(operations, Side::Written, &mut header[..len]);
+        Header {
+            command,
+            opcode,
+            address: has_address.then(|| u32::from_be_bytes([0, header[1], header[2], header[3]])),
+            dummy_bytes,
+            len,
+            written,
+        }
+    }
+
+    /// Complete the decoding once the transaction ran
+    fn event(&self, operations: &[Operation<'_, u8>], failed: bool) -> Event {
+        let read = scan(operations, Side::Read, &mut [0; HEADER_MAX_LEN][..self.len]);
+        let mut event = Event {
+            opcode: self.opcode,
+            name: self.command.map(Command::name),
+            address: self.address,
+            dummy_bytes: self.dummy_bytes,
+            written: self.written.len,
+            read: read.len,
+            status: None,
+            configuration: None,
+            security: None,
+            failed,
+        };
+        if failed {
+            return event;
+        }
+
+        let written = &self.written;
+        match self.command {
+            Some(Command::ReadStatus) if read.len >= 1 => {
+                event.status = Some(read.first[0].into());
+            }
+            Some(Command::ReadConfig) if read.len >= 2 => {
+                event.configuration = Some([read.first[0], read.first[1]].into());
+            }
+            Some(Command::ReadSecurityRegister) if read.len >= 1 => {
+                event.security = Some(read.first[0].into());
+            }
+            Some(Command::WriteStatus) => {
+                if written.len >= 1 {
+                    event.status = Some(written.first[0].into());
+                }
+                if written.len >= 3 {
+                    event.configuration = Some([written.first[1], written.first[2]].into());
+                }
+            }
+            _ => {}
+        }
+        event
+    }
+}
@@ -0,0 +1,1267 @@
+//! The scenarios of `nrf52840/tests/asynchronous.rs` and the rest of the `AsyncMX25R` API, run on the simulator.
+
+use std::sync::mpsc;
+
+use embassy_futures::{block_on, poll_once};
+use embedded_hal::spi::{ErrorType, Operation as SpiOperation};
+use embedded_hal_async::spi::SpiDevice;
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use mx25r::{
+    asynchronous::{AsyncDynamicMX25R, AsyncMX25R6435F},
+    bus::ReadMode,
+    erase::{ErasePlan, EraseStep},
+    error::Error,
+    part::Part,
+    poll::{Backoff, FixedInterval, PollStrategy, Yield},
+    power::PowerThresholds,
+    register::{BurstLength, PowerMode, ProtectedArea},
+    sim::{SimError, SimTimings, Simulator, VirtualClock},
+    timing::Operation,
+    trace::{Event, Trace},
+    BLOCK32_SIZE, BLOCK64_SIZE, DEEP_POWER_DOWN_RECOVERY_US, PAGE_SIZE, SECTOR_SIZE,
+    SECURE_OTP_SIZE,
+};
+
+type Flash<P = Backoff> = AsyncMX25R6435F<Simulator, VirtualClock, P>;
+
+const CAPACITY: u32 = 8 * 1024 * 1024;
+
+/// A blank chip with the typical durations of the datasheet
+fn memory() -> Flash {
+    memory_with(SimTimings::typical(Part::MX25R6435F))
+}
+
+fn memory_with(timings: SimTimings) -> Flash {
+    memory_and_clock(timings, Backoff::default()).0
+}
+
+/// The clock is shared by the simulator and the driver, a clone advances them both
+fn memory_and_clock<P: PollStrategy>(timings: SimTimings, poll: P) -> (Flash<P>, VirtualClock) {
+    let clock = VirtualClock::new();
+    let mut sim = Simulator::new(Part::MX25R6435F);
+    sim.set_timing(clock.clone(), timings);
+    (AsyncMX25R6435F::new(sim, clock.clone(), poll), clock)
+}
+
+#[test]
+fn basic() {
+    block_on(async {
+        let mut memory = memory();
+        let mut buff = [0];
+        let addr = 0;
+
+        memory.read(addr, &mut buff).await.unwrap();
+        memory
+            .erase_sector(addr)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        memory.read(addr, &mut buff).await.unwrap();
+        assert_eq!(buff[0], 0xff);
+
+        memory
+            .write_page(0, &[42])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        memory.read(addr, &mut buff).await.unwrap();
+        assert_eq!(buff[0], 42);
+
+        memory
+            .erase_sector(addr)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+    })
+}
+
+/// Read multiple bytes in a single call.
+#[test]
+fn read_multiple_bytes() {
+    block_on(async {
+        let mut memory = memory();
+        const LEN: usize = 16;
+        let mut buf = [0u8; LEN];
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        let mut pattern = [0u8; LEN];
+        for (i, e) in pattern.iter_mut().enumerate() {
+            *e = i as u8;
+        }
+        memory
+            .write_page(0, &pattern)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory.read(0, &mut buf).await.unwrap();
+        assert_eq!(&buf, &pattern);
+    })
+}
+
+/// Read spanning one sector end into the next.
+#[test]
+fn read_across_sector_boundary() {
+    block_on(async {
+        let mut memory = memory();
+        let mut buf = [0u8; 16];
+
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory
+            .write_page(SECTOR_SIZE, &[1; 8])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory.read(SECTOR_SIZE - 8, &mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 1, 1, 1, 1, 1, 1, 1]
+        );
+    })
+}
+
+/// Out-of-bounds reads should error.
+#[test]
+fn read_out_of_bounds() {
+    block_on(async {
+        let mut memory = memory();
+        let mut buf = [0u8; 16];
+        let res = memory.read(0x4000_0000, &mut buf).await;
+        assert!(matches!(res, Err(Error::OutOfBounds)));
+    })
+}
+
+/// Directly exercise the ReadNorFlash trait’s `read` method with an absolute offset.
+#[test]
+fn direct_trait_read() {
+    block_on(async {
+        let mut memory = memory();
+        let mut buf = [0u8; 4];
+
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory
+            .write_page(0, &[1, 2, 3, 4])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+    })
+}
+
+/// Check that `capacity()` returns the expected total size.
+#[test]
+fn trait_capacity() {
+    block_on(async {
+        let memory = memory();
+        assert_eq!(ReadNorFlash::capacity(&memory), CAPACITY as usize);
+    })
+}
+
+/// Write a blob at an arbitrary offset via the `NorFlash` & `ReadNorFlash` traits, then read it back.
+#[test]
+fn trait_write_read() {
+    block_on(async {
+        let mut memory = memory();
+        const DATA: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
+        let mut buf = [0u8; 4];
+
+        NorFlash::erase(&mut memory, 0, SECTOR_SIZE).await.unwrap();
+        NorFlash::write(&mut memory, 0, &DATA).await.unwrap();
+        ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
+        assert_eq!(buf, DATA);
+    })
+}
+
+/// Erase a region that spans two sectors via the `NorFlash` and `ReadNorFlash` traits,
+/// then verify before/after.
+#[test]
+fn trait_erase_range() {
+    block_on(async {
+        let mut memory = memory();
+        const LEN: usize = SECTOR_SIZE as usize + (SECTOR_SIZE / 2) as usize;
+        const ERASE: u32 = 2 * SECTOR_SIZE;
+        let mut buf = [0u8; LEN];
+        let data = [0x55u8; LEN];
+
+        NorFlash::erase(&mut memory, 0, ERASE).await.unwrap();
+
+        ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
+        assert!(buf.iter().all(|&b| b == 0xFF));
+
+        NorFlash::write(&mut memory, 0, &data).await.unwrap();
+
+        ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
+        assert!(buf.iter().all(|&b| b == 0x55));
+
+        NorFlash::erase(&mut memory, 0, ERASE).await.unwrap();
+
+        ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
+        assert!(buf.iter().all(|&b| b == 0xFF));
+    })
+}
+
+/// Writes crossing the pages are split by the `NorFlash` implementation
+#[test]
+fn trait_write_across_pages() {
+    block_on(async {
+        let mut memory = memory();
+        let data: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| i as u8).collect();
+        let mut buf = vec![0; data.len()];
+
+        NorFlash::write(&mut memory, PAGE_SIZE / 2, &data)
+            .await
+            .unwrap();
+        ReadNorFlash::read(&mut memory, PAGE_SIZE / 2, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, data);
+        assert!(matches!(
+            NorFlash::erase(&mut memory, 0, SECTOR_SIZE / 2).await,
+            Err(Error::NotAligned)
+        ));
+    })
+}
+
+/// Decode the JEDEC Basic Flash Parameter Table and check it against the expected part.
+#[test]
+fn sfdp_basic_flash_parameters() {
+    block_on(async {
+        let mut memory = memory();
+        let header = memory.read_sfdp_header().await.unwrap();
+        assert!(header.parameter_headers() >= 1);
+
+        let params = memory.read_basic_flash_parameters().await.unwrap();
+        assert_eq!(params.capacity(), CAPACITY as u64);
+        assert_eq!(params.erase_4k_opcode, Some(0x20));
+        assert_eq!(params.fast_read.read_1_4_4.unwrap().opcode, 0xEB);
+        assert_eq!(params.page_size, Some(256));
+    })
+}
+
+#[test]
+fn sfdp_raw() {
+    block_on(async {
+        let mut memory = memory();
+        let mut signature = [0; 4];
+        memory.read_sfdp(0, &mut signature).await.unwrap();
+        assert_eq!(&signature, b"SFDP");
+
+        let header = memory.read_sfdp_parameter_header(0).await.unwrap();
+        assert!(header.is_basic_flash_parameters());
+        assert!(header.length >= 9);
+    })
+}
+
+/// Protect the top of the array and check the reported range, then remove the protection.
+#[test]
+fn protect_top_range() {
+    block_on(async {
+        let mut memory = memory();
+        // The smallest setting protects 1/64 of the array
+        let range = CAPACITY - CAPACITY / 64..CAPACITY;
+
+        memory.protect_range(range.clone()).await.unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(memory.protected_range().await.unwrap(), range);
+
+        memory.protect_range(0..0).await.unwrap();
+        memory.wait_wip().await.unwrap();
+        assert!(memory.protected_range().await.unwrap().is_empty());
+    })
+}
+
+/// Protect the smallest area covering a range, without touching the top/bottom bit.
+#[test]
+fn protect_covering_range() {
+    block_on(async {
+        let mut memory = memory();
+
+        let protection = memory
+            .protect_range(CAPACITY - 100..CAPACITY)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(
+            memory.protected_range().await.unwrap(),
+            CAPACITY - CAPACITY / 64..CAPACITY
+        );
+        assert_eq!(protection.area, ProtectedArea::Top);
+
+        // Only the whole chip covers it from the top, the bottom must be selected
+        assert!(matches!(
+            memory.protect_range(0..CAPACITY / 64).await,
+            Err(Error::Value)
+        ));
+        assert_eq!(
+            memory.protected_range().await.unwrap(),
+            CAPACITY - CAPACITY / 64..CAPACITY
+        );
+        memory.protect_range(0..CAPACITY).await.unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(memory.protected_range().await.unwrap(), 0..CAPACITY);
+        assert_eq!(
+            memory.read_configuration().await.unwrap().protected_section,
+            ProtectedArea::Top
+        );
+
+        assert!(matches!(
+            memory.protect_range(0..CAPACITY + 1).await,
+            Err(Error::Value)
+        ));
+    })
+}
+
+#[test]
+fn protect_bottom_range() {
+    block_on(async {
+        let mut memory = memory();
+        let range = 0..CAPACITY / 64;
+
+        let protection = memory
+            .protect_range_in(range.clone(), ProtectedArea::Bottom)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(protection.area, ProtectedArea::Bottom);
+        assert_eq!(memory.read_block_protection().await.unwrap(), protection);
+        assert_eq!(memory.protected_range().await.unwrap(), range);
+        // The top/bottom bit is one time programmable
+        assert!(matches!(
+            memory
+                .protect_range_in(CAPACITY - CAPACITY / 64..CAPACITY, ProtectedArea::Top)
+                .await,
+            Err(Error::Value)
+        ));
+    })
+}
+
+#[test]
+fn failure_detection() {
+    block_on(async {
+        let mut memory = memory();
+        assert!(!memory.failure_detection());
+        memory.set_failure_detection(true);
+        assert!(memory.failure_detection());
+
+        let top = CAPACITY - SECTOR_SIZE;
+        memory
+            .protect_range(CAPACITY - CAPACITY / 64..CAPACITY)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert!(matches!(
+            NorFlash::write(&mut memory, top, &[0]).await,
+            Err(Error::ProgramFailed { addr }) if addr == top
+        ));
+        assert!(matches!(
+            NorFlash::erase(&mut memory, top, CAPACITY).await,
+            Err(Error::EraseFailed { addr }) if addr == top
+        ));
+        NorFlash::write(&mut memory, 0, &[0]).await.unwrap();
+    })
+}
+
+/// The next program finds the failed one completed, both it and the token report the failure
+#[test]
+fn failure_reported_by_next_operation() {
+    block_on(async {
+        let mut memory = memory();
+        memory.set_failure_detection(true);
+        let top = CAPACITY - SECTOR_SIZE;
+        memory
+            .protect_range(CAPACITY - CAPACITY / 64..CAPACITY)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+
+        let program = memory.write_page(top, &[0]).await.unwrap();
+        assert!(matches!(
+            memory.write_page(0, &[0]).await,
+            Err(Error::ProgramFailed { addr }) if addr == top
+        ));
+        assert!(matches!(
+            program.wait(&mut memory).await,
+            Err(Error::ProgramFailed { addr }) if addr == top
+        ));
+        memory
+            .write_page(0, &[0])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+    })
+}
+
+#[test]
+fn secure_otp_read() {
+    block_on(async {
+        let mut memory = memory();
+        let mut array = [0; 16];
+        memory.read_fast(0, &mut array).await.unwrap();
+
+        let mut buff = [0; 16];
+        let mut otp = memory.secure_otp().await.unwrap();
+        otp.read(0, &mut buff).await.unwrap();
+        assert!(matches!(
+            otp.read(SECURE_OTP_SIZE - 8, &mut buff).await,
+            Err(Error::OutOfBounds)
+        ));
+        otp.exit().await.unwrap();
+
+        // Dropping the guard exits the OTP as well
+        let otp = memory.secure_otp().await.unwrap();
+        drop(otp);
+        memory.read_fast(0, &mut buff).await.unwrap();
+        assert_eq!(buff, array);
+    })
+}
+
+#[test]
+fn secure_otp_program_and_lock() {
+    block_on(async {
+        let mut memory = memory();
+        let mut otp = memory.secure_otp().await.unwrap();
+        otp.program(PAGE_SIZE - 2, &[1, 2, 3, 4]).await.unwrap();
+        let mut buff = [0; 4];
+        otp.read(PAGE_SIZE - 2, &mut buff).await.unwrap();
+        assert_eq!(buff, [1, 2, 3, 4]);
+        otp.exit().await.unwrap();
+
+        // The array is untouched
+        memory.read(PAGE_SIZE - 2, &mut buff).await.unwrap();
+        assert_eq!(buff, [0xFF; 4]);
+
+        // The lock down waits for the program in progress, it needs the write enable sent once the chip is ready
+        let program = memory.write_page(0, &[0]).await.unwrap();
+        assert!(matches!(
+            memory.write_security_register().await,
+            Err(Error::Busy)
+        ));
+        program.wait(&mut memory).await.unwrap();
+        assert!(!memory.read_security_register().await.unwrap().locked_down);
+
+        memory.write_security_register().await.unwrap();
+        memory.wait_wip().await.unwrap();
+        assert!(memory.read_security_register().await.unwrap().locked_down);
+
+        // The raw instructions, the lock down refuses the program
+        memory.enter_secure_opt().await.unwrap();
+        memory
+            .write_page(0, &[0])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        assert!(
+            memory
+                .read_security_register()
+                .await
+                .unwrap()
+                .program_failed
+        );
+        memory.read(0, &mut buff[..1]).await.unwrap();
+        assert_eq!(buff[0], 0xFF);
+        memory.exit_secure_opt().await.unwrap();
+    })
+}
+
+/// Drop the guard while an OTP program is in progress, the deferred exit waits for the program before the array is read
+#[test]
+fn secure_otp_drop_during_program() {
+    block_on(async {
+        let (mut memory, _) = memory_and_clock(SimTimings::typical(Part::MX25R6435F), Yield);
+        memory
+            .write_page(0, &[42])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        let mut otp = memory.secure_otp().await.unwrap();
+        // The program is sent, then its wait yields and the future is dropped
+        assert!(poll_once(otp.program(0, &[24])).is_pending());
+        drop(otp);
+        // The poll doesn't flush the exit, the next instruction waits for the program before sending it
+        assert!(matches!(memory.poll_wip().await, Err(Error::Busy)));
+        assert!(!memory.read_status().await.unwrap().wip_bit);
+
+        let mut buff = [0];
+        memory.read(0, &mut buff).await.unwrap();
+        assert_eq!(buff, [42]);
+
+        let mut otp = memory.secure_otp().await.unwrap();
+        otp.read(0, &mut buff).await.unwrap();
+        assert_eq!(buff, [24]);
+        otp.exit().await.unwrap();
+    })
+}
+
+#[test]
+fn deep_power_down_wake() {
+    block_on(async {
+        let (memory, clock) =
+            memory_and_clock(SimTimings::typical(Part::MX25R6435F), Backoff::default());
+        let sleeping = memory.deep_power_down().await.ok().unwrap();
+        let start = clock.now_us();
+        let mut memory = sleeping.wake().await.ok().unwrap();
+        assert!(clock.now_us() - start >= u64::from(DEEP_POWER_DOWN_RECOVERY_US));
+
+        let (manufacturer, _, _) = memory.read_identification().await.unwrap();
+        assert_eq!(manufacturer.0, 0xC2);
+    })
+}
+
+#[test]
+fn pending_operation_refuses_conflicts() {
+    block_on(async {
+        let mut memory = memory();
+        let erase = memory.erase_sector(0).await.unwrap();
+        assert_eq!(erase.operation(), Operation::SectorErase);
+        assert_eq!(erase.addr(), 0);
+        assert!(matches!(
+            memory.write_page(0, &[42]).await,
+            Err(Error::Busy)
+        ));
+
+        while let Err(Error::Busy) = erase.poll(&mut memory).await {}
+        erase.poll(&mut memory).await.unwrap();
+        memory
+            .write_page(0, &[42])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+    })
+}
+
+/// A token polled to completion is stale once another program or erase starts, it doesn't wait for it
+#[test]
+fn stale_token() {
+    block_on(async {
+        let mut memory = memory();
+        let erase = memory.erase_sector(0).await.unwrap();
+        while let Err(Error::Busy) = erase.poll(&mut memory).await {}
+
+        let program = memory.write_page(SECTOR_SIZE, &[42]).await.unwrap();
+        assert!(matches!(erase.poll(&mut memory).await, Err(Error::Value)));
+        assert!(matches!(erase.wait(&mut memory).await, Err(Error::Value)));
+        assert!(matches!(program.poll(&mut memory).await, Err(Error::Busy)));
+        program.wait(&mut memory).await.unwrap();
+    })
+}
+
+#[test]
+fn read_while_erasing() {
+    block_on(async {
+        let mut memory = memory();
+        memory
+            .write_page(0, &[1; 16])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        let mut expected = [0; 16];
+        memory.read_fast(0, &mut expected).await.unwrap();
+
+        let mut buff = [0; 16];
+        let erase = memory.erase_block64(BLOCK64_SIZE).await.unwrap();
+        memory.read_while_erasing(0, &mut buff).await.unwrap();
+        assert_eq!(buff, expected);
+        assert!(matches!(
+            memory.read_while_erasing(BLOCK64_SIZE, &mut buff).await,
+            Err(Error::Busy)
+        ));
+        erase.wait(&mut memory).await.unwrap();
+    })
+}
+
+/// Fails the first transaction sending `opcode` with a bus error, the others reach the simulator
+struct FailOnce {
+    sim: Simulator,
+    opcode: Option<u8>,
+}
+
+impl ErrorType for FailOnce {
+    type Error = SimError;
+}
+
+impl SpiDevice for FailOnce {
+    async fn transaction(
+        &mut self,
+        operations: &mut [SpiOperation<'_, u8>],
+    ) -> Result<(), SimError> {
+        if let [SpiOperation::Write(header), ..] = operations {
+            if self
+                .opcode
+                .is_some_and(|opcode| header.first() == Some(&opcode))
+            {
+                self.opcode = None;
+                return Err(SimError::Bus);
+            }
+        }
+        self.sim.transaction(operations).await
+    }
+}
+
+/// A bus error once the erase is suspended fails the read, the erase is resumed anyway
+#[test]
+fn read_while_erasing_resumes_on_error() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let mut sim = Simulator::new(Part::MX25R6435F);
+        sim.set_timing(clock.clone(), SimTimings::typical(Part::MX25R6435F));
+        // The security register is first read once the erase is suspended
+        let bus = FailOnce {
+            sim,
+            opcode: Some(0x2B),
+        };
+        let mut memory = AsyncMX25R6435F::new(bus, clock, Backoff::default());
+
+        let erase = memory.erase_block64(BLOCK64_SIZE).await.unwrap();
+        let mut buff = [0; 16];
+        assert!(matches!(
+            memory.read_while_erasing(0, &mut buff).await,
+            Err(Error::Spi(SimError::Bus))
+        ));
+        assert!(memory.read_status().await.unwrap().wip_bit);
+        assert!(
+            !memory
+                .read_security_register()
+                .await
+                .unwrap()
+                .erase_suspended
+        );
+        erase.wait(&mut memory).await.unwrap();
+        memory.read(BLOCK64_SIZE, &mut buff).await.unwrap();
+        assert_eq!(buff, [0xFF; 16]);
+    })
+}
+
+#[test]
+fn suspend_resume() {
+    block_on(async {
+        let mut memory = memory();
+        let erase = memory.erase_sector(0).await.unwrap();
+        memory.suspend_program_erase().await.unwrap();
+        memory.wait_wip().await.unwrap();
+        assert!(
+            memory
+                .read_security_register()
+                .await
+                .unwrap()
+                .erase_suspended
+        );
+
+        memory.resume_program_erase().await.unwrap();
+        assert!(memory.read_status().await.unwrap().wip_bit);
+        assert!(
+            !memory
+                .read_security_register()
+                .await
+                .unwrap()
+                .erase_suspended
+        );
+        // The resumed erase is pending again, the token waits for it
+        erase.wait(&mut memory).await.unwrap();
+        assert!(!memory.read_status().await.unwrap().wip_bit);
+    })
+}
+
+#[test]
+fn read_wrapped() {
+    block_on(async {
+        let mut memory = memory();
+        let pattern: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+        memory
+            .erase_sector(0)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        memory
+            .write_page(0, &pattern)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        let mut buff = [0; 8];
+        assert!(matches!(
+            memory.read_wrapped(4, &mut buff).await,
+            Err(Error::Value)
+        ));
+        memory.set_burst_length(BurstLength::Wrap8).await.unwrap();
+        assert_eq!(memory.burst_length(), BurstLength::Wrap8);
+        memory.read_wrapped(4, &mut buff).await.unwrap();
+        assert_eq!(buff, [4, 5, 6, 7, 0, 1, 2, 3]);
+        memory
+            .set_burst_length(BurstLength::Disabled)
+            .await
+            .unwrap();
+    })
+}
+
+/// The chip ignores the burst length while busy, it's refused during an erase and sent once the chip is ready
+#[test]
+fn burst_length_while_busy() {
+    block_on(async {
+        let mut memory = memory();
+        memory
+            .write_page(0, &[0, 1, 2, 3, 4, 5, 6, 7])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+
+        let erase = memory.erase_sector(SECTOR_SIZE * 16).await.unwrap();
+        assert!(matches!(
+            memory.set_burst_length(BurstLength::Wrap8).await,
+            Err(Error::Busy)
+        ));
+        assert_eq!(memory.burst_length(), BurstLength::Disabled);
+        erase.wait(&mut memory).await.unwrap();
+
+        // A register write without token is waited for
+        memory.modify_status(|_| {}).await.unwrap();
+        memory.set_burst_length(BurstLength::Wrap8).await.unwrap();
+        let mut buff = [0; 8];
+        memory.read_wrapped(4, &mut buff).await.unwrap();
+        assert_eq!(buff, [4, 5, 6, 7, 0, 1, 2, 3]);
+    })
+}
+
+/// The transfers over the thresholds switch to the high performance mode and back, as traced on the bus
+#[test]
+fn managed_power_mode() {
+    block_on(async {
+        let (events, received) = mpsc::channel();
+        let bus = Trace::new(Simulator::new(Part::MX25R6435F), move |event: &Event| {
+            events.send(*event).unwrap()
+        });
+        let mut memory = AsyncMX25R6435F::new(bus, VirtualClock::new(), Backoff::default());
+        assert_eq!(memory.power_management(), None);
+        memory
+            .set_power_mode(PowerMode::UltraLowPower)
+            .await
+            .unwrap();
+        let thresholds = PowerThresholds {
+            read: 16,
+            write: 16,
+        };
+        memory.set_power_management(Some(thresholds));
+        assert_eq!(memory.power_management(), Some(thresholds));
+
+        let pattern = [0x5A; 32];
+        let mut buff = [0; 32];
+        NorFlash::erase(&mut memory, 0, SECTOR_SIZE).await.unwrap();
+        NorFlash::write(&mut memory, 0, &pattern).await.unwrap();
+        ReadNorFlash::read(&mut memory, 0, &mut buff).await.unwrap();
+        // Below the thresholds
+        ReadNorFlash::read(&mut memory, 0, &mut buff[..8])
+            .await
+            .unwrap();
+        assert_eq!(buff, pattern);
+        let modes: Vec<_> = received
+            .try_iter()
+            .filter(|event| event.name == Some("WRSR"))
+            .filter_map(|event| event.configuration.map(|config| config.power_mode))
+            .collect();
+        assert_eq!(
+            modes,
+            [
+                PowerMode::UltraLowPower,
+                PowerMode::HighPerformance,
+                PowerMode::UltraLowPower,
+                PowerMode::HighPerformance,
+                PowerMode::UltraLowPower,
+            ]
+        );
+        assert_eq!(memory.power_mode(), Some(PowerMode::UltraLowPower));
+        assert_eq!(
+            memory.read_configuration().await.unwrap().power_mode,
+            PowerMode::UltraLowPower
+        );
+    })
+}
+
+#[test]
+fn trait_erase_unaligned_blocks() {
+    block_on(async {
+        let mut memory = memory();
+        const FROM: u32 = SECTOR_SIZE;
+        const TO: u32 = SECTOR_SIZE + BLOCK64_SIZE;
+
+        // 7 sectors up to the first 32K block, the 32K block and the last sector
+        let plan = ErasePlan::new(FROM, TO).unwrap();
+        assert_eq!(plan.count(), 9);
+
+        NorFlash::write(&mut memory, 0, &[0; 32]).await.unwrap();
+        NorFlash::write(&mut memory, TO - 16, &[0; 32])
+            .await
+            .unwrap();
+        NorFlash::erase(&mut memory, FROM, TO).await.unwrap();
+        let mut buf = [0u8; 16];
+        ReadNorFlash::read(&mut memory, TO - 16, &mut buf)
+            .await
+            .unwrap();
+        assert!(buf.iter().all(|&b| b == 0xFF));
+        // Outside of the range
+        ReadNorFlash::read(&mut memory, 0, &mut buf).await.unwrap();
+        assert_eq!(buf, [0; 16]);
+        ReadNorFlash::read(&mut memory, TO, &mut buf).await.unwrap();
+        assert_eq!(buf, [0; 16]);
+    })
+}
+
+#[test]
+fn erase_alignment() {
+    block_on(async {
+        let mut memory = memory();
+        assert!(matches!(
+            memory.erase_sector(1).await,
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            memory.erase_block32(SECTOR_SIZE).await,
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            memory.erase_block64(BLOCK32_SIZE).await,
+            Err(Error::NotAligned)
+        ));
+        assert!(matches!(
+            memory.erase_block64(CAPACITY).await,
+            Err(Error::OutOfBounds)
+        ));
+
+        NorFlash::write(&mut memory, BLOCK32_SIZE, &[0; 4])
+            .await
+            .unwrap();
+        memory
+            .erase_block32(BLOCK32_SIZE)
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        let op = memory.erase_step(EraseStep::Block64(0)).await.unwrap();
+        assert_eq!(op.operation(), Operation::Block64Erase);
+        op.wait(&mut memory).await.unwrap();
+        let mut buf = [0; 4];
+        memory.read(BLOCK32_SIZE, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xFF; 4]);
+    })
+}
+
+#[test]
+fn erase_chip() {
+    block_on(async {
+        let mut memory = memory();
+        NorFlash::write(&mut memory, CAPACITY - 4, &[0; 4])
+            .await
+            .unwrap();
+        let erase = memory.erase_chip().await.unwrap();
+        assert_eq!(erase.operation(), Operation::ChipErase);
+        erase.wait(&mut memory).await.unwrap();
+        let mut buf = [0; 4];
+        memory.read(CAPACITY - 4, &mut buf).await.unwrap();
+        assert_eq!(buf, [0xFF; 4]);
+    })
+}
+
+#[test]
+fn properties() {
+    block_on(async {
+        let memory = memory();
+        assert_eq!(memory.capacity(), CAPACITY as usize);
+        assert_eq!(memory.part(), Some(Part::MX25R6435F));
+        assert_eq!(memory.timings(), Part::MX25R6435F.max_timings());
+        assert_eq!(memory.typical_timings(), Part::MX25R6435F.typical_timings());
+        assert_eq!(memory.verify_addr(CAPACITY - 1).unwrap(), CAPACITY - 1);
+        assert!(matches!(
+            memory.verify_addr(CAPACITY),
+            Err(Error::OutOfBounds)
+        ));
+    })
+}
+
+#[test]
+fn read_modes() {
+    block_on(async {
+        let mut memory = memory();
+        NorFlash::write(&mut memory, 0, &[1, 2, 3, 4])
+            .await
+            .unwrap();
+
+        let mut normal = [0; 4];
+        let mut fast = [0; 4];
+        memory.read(0, &mut normal).await.unwrap();
+        memory.read_fast(0, &mut fast).await.unwrap();
+        assert_eq!(normal, [1, 2, 3, 4]);
+        assert_eq!(fast, normal);
+
+        // A SPI device only has a single data line
+        let mut buff = [0; 4];
+        assert!(matches!(
+            memory.read_dual_output(0, &mut buff).await,
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            memory.read_dual_io(0, &mut buff).await,
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            memory.read_quad_output(0, &mut buff).await,
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            memory.read_quad_io(0, &mut buff).await,
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            memory.write_page_quad(0, &[0]).await,
+            Err(Error::Unsupported)
+        ));
+
+        assert_eq!(memory.read_mode(), ReadMode::Fast);
+        assert!(matches!(
+            memory.set_read_mode(ReadMode::QuadIo),
+            Err(Error::Unsupported)
+        ));
+        memory.set_read_mode(ReadMode::Normal).unwrap();
+        assert_eq!(memory.read_mode(), ReadMode::Normal);
+        memory.read_with_mode(0, &mut buff).await.unwrap();
+        assert_eq!(buff, [1, 2, 3, 4]);
+    })
+}
+
+#[test]
+fn registers() {
+    block_on(async {
+        let mut memory = memory();
+        memory.write_disable().await.unwrap();
+        assert!(!memory.read_status().await.unwrap().write_enable_latch);
+
+        let status = memory
+            .modify_status(|status| status.protected_block = 1)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(memory.read_status().await.unwrap(), status);
+
+        let config = memory
+            .modify_configuration(|config| config.dummmy_cycle = true)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(memory.read_configuration().await.unwrap(), config);
+
+        let mut status = memory.read_status().await.unwrap();
+        status.protected_block = 0;
+        memory.write_status(status).await.unwrap();
+        memory.wait_wip().await.unwrap();
+        assert!(memory.protected_range().await.unwrap().is_empty());
+
+        status.protected_block = 0x10;
+        assert!(matches!(
+            memory.write_status(status).await,
+            Err(Error::Value)
+        ));
+
+        let mut config = memory.read_configuration().await.unwrap();
+        config.power_mode = PowerMode::HighPerformance;
+        status.protected_block = 0;
+        memory
+            .write_status_configuration(status, config)
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(memory.read_configuration().await.unwrap(), config);
+
+        #[allow(deprecated)]
+        memory
+            .write_configuration(
+                0,
+                false,
+                false,
+                false,
+                ProtectedArea::Top,
+                PowerMode::UltraLowPower,
+            )
+            .await
+            .unwrap();
+        memory.wait_wip().await.unwrap();
+        assert_eq!(
+            memory.read_configuration().await.unwrap().power_mode,
+            PowerMode::UltraLowPower
+        );
+    })
+}
+
+#[test]
+fn identification() {
+    block_on(async {
+        let mut memory = memory();
+        let (manufacturer, memory_type, density) = memory.read_identification().await.unwrap();
+        assert_eq!(
+            Part::from_identification(manufacturer, memory_type, density),
+            Some(Part::MX25R6435F)
+        );
+        assert_eq!(memory.read_electronic_id().await.unwrap().0, density.0);
+        let (manufacturer, device) = memory.read_manufacturer_id().await.unwrap();
+        assert_eq!(manufacturer.0, 0xC2);
+        assert_eq!(device.0, density.0);
+    })
+}
+
+#[test]
+fn reset() {
+    block_on(async {
+        let mut memory = memory();
+        memory.nop().await.unwrap();
+        memory.set_burst_length(BurstLength::Wrap16).await.unwrap();
+
+        // A lone reset enable is cancelled by the next instruction
+        memory.reset_enable().await.unwrap();
+        memory.nop().await.unwrap();
+        memory.reset().await.unwrap();
+        assert_eq!(memory.burst_length(), BurstLength::Disabled);
+
+        // The wraps are disabled on the chip as well
+        NorFlash::write(&mut memory, 0, &[1; 16]).await.unwrap();
+        let mut buff = [0; 32];
+        memory.read(0, &mut buff).await.unwrap();
+        assert_eq!(buff[..16], [1; 16]);
+        assert_eq!(buff[16..], [0xFF; 16]);
+    })
+}
+
+#[test]
+fn detect() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let mut sim = Simulator::new(Part::MX25R1635F);
+        sim.set_timing(clock.clone(), SimTimings::typical(Part::MX25R1635F));
+        let mut memory = AsyncDynamicMX25R::detect(sim, clock, Backoff::default())
+            .await
+            .unwrap();
+        assert_eq!(memory.part(), Some(Part::MX25R1635F));
+        assert_eq!(memory.capacity(), Part::MX25R1635F.capacity());
+
+        let last = memory.capacity() as u32 - 4;
+        NorFlash::write(&mut memory, last, &[1, 2, 3, 4])
+            .await
+            .unwrap();
+        let mut buff = [0; 4];
+        memory.read(last, &mut buff).await.unwrap();
+        assert_eq!(buff, [1, 2, 3, 4]);
+        assert!(matches!(
+            memory.read(last + 4, &mut buff).await,
+            Err(Error::OutOfBounds)
+        ));
+
+        let memory = AsyncDynamicMX25R::new_with_part(
+            Simulator::new(Part::MX25R512F),
+            VirtualClock::new(),
+            Backoff::default(),
+            Part::MX25R512F,
+        );
+        assert_eq!(memory.capacity(), Part::MX25R512F.capacity());
+    })
+}
+
+#[test]
+fn wait_for_datasheet_durations() {
+    block_on(async {
+        let (mut memory, clock) =
+            memory_and_clock(SimTimings::typical(Part::MX25R6435F), Backoff::default());
+
+        let program = memory.write_page(0, &[0]).await.unwrap();
+        assert!(matches!(program.poll(&mut memory).await, Err(Error::Busy)));
+        assert!(matches!(memory.poll_wip().await, Err(Error::Busy)));
+        program.wait(&mut memory).await.unwrap();
+        memory.poll_wip().await.unwrap();
+        let typical = u64::from(Part::MX25R6435F.typical_timings().page_program_us);
+        assert!((typical..typical + 100).contains(&clock.now_us()));
+    })
+}
+
+#[test]
+fn timeout() {
+    block_on(async {
+        let mut timings = SimTimings::max(Part::MX25R6435F);
+        timings.operations.sector_erase_us *= 2;
+        let mut memory = memory_with(timings);
+
+        let erase = memory.erase_sector(0).await.unwrap();
+        assert!(matches!(erase.wait(&mut memory).await, Err(Error::Timeout)));
+        // The chip completes eventually
+        memory.wait_wip().await.unwrap();
+    })
+}
+
+#[test]
+fn poll_fixed_interval() {
+    block_on(async {
+        let (mut memory, clock) =
+            memory_and_clock(SimTimings::typical(Part::MX25R6435F), FixedInterval(1_000));
+
+        let erase = memory.erase_sector(0).await.unwrap();
+        erase.wait(&mut memory).await.unwrap();
+        let typical = u64::from(Part::MX25R6435F.typical_timings().sector_erase_us);
+        assert!((typical..typical + 1_100).contains(&clock.now_us()));
+    })
+}
+
+#[test]
+fn poll_backoff_limit() {
+    block_on(async {
+        let backoff = Backoff {
+            initial_us: 10,
+            max_us: 500,
+        };
+        let (mut memory, clock) = memory_and_clock(SimTimings::max(Part::MX25R6435F), backoff);
+
+        // The sector erase lasts far past its typical duration, the polls then stay 500us apart
+        let erase = memory.erase_sector(0).await.unwrap();
+        erase.wait(&mut memory).await.unwrap();
+        let max = u64::from(Part::MX25R6435F.max_timings().sector_erase_us);
+        assert!((max..max + 600).contains(&clock.now_us()));
+    })
+}
+
+#[test]
+fn poll_yield() {
+    block_on(async {
+        let (mut memory, _) = memory_and_clock(SimTimings::typical(Part::MX25R6435F), Yield);
+
+        // Only the bus transfers advance the clock
+        let program = memory.write_page(0, &[0x5A]).await.unwrap();
+        program.wait(&mut memory).await.unwrap();
+        let mut buff = [0];
+        memory.read(0, &mut buff).await.unwrap();
+        assert_eq!(buff, [0x5A]);
+    })
+}
+
+/// The yields count towards the limit, a chip stuck busy times out
+#[test]
+fn poll_yield_timeout() {
+    block_on(async {
+        let mut timings = SimTimings::typical(Part::MX25R6435F);
+        timings.operations.page_program_us = u32::MAX;
+        let (mut memory, _) = memory_and_clock(timings, Yield);
+
+        let program = memory.write_page(0, &[0x5A]).await.unwrap();
+        assert!(matches!(
+            program.wait(&mut memory).await,
+            Err(Error::Timeout)
+        ));
+    })
+}
+
+#[test]
+fn trace() {
+    block_on(async {
+        let (events, received) = mpsc::channel();
+        let bus = Trace::new(Simulator::new(Part::MX25R6435F), move |event: &Event| {
+            events.send(*event).unwrap()
+        });
+        let mut memory = AsyncMX25R6435F::new(bus, VirtualClock::new(), Backoff::default());
+
+        let status = memory.read_status().await.unwrap();
+        let event = received.try_recv().unwrap();
+        assert_eq!(event.opcode, 0x05);
+        assert_eq!(event.name, Some("RDSR"));
+        assert_eq!(event.address, None);
+        assert_eq!((event.written, event.read), (0, 1));
+        assert_eq!(event.status, Some(status));
+        assert!(!event.failed);
+
+        memory
+            .write_page(0x1234, &[1, 2, 3])
+            .await
+            .unwrap()
+            .wait(&mut memory)
+            .await
+            .unwrap();
+        let events: Vec<_> = received.try_iter().collect();
+        let names: Vec<_> = events.iter().filter_map(|event| event.name).collect();
+        assert!(names.starts_with(&["RDSR", "WREN", "PP"]));
+        let program = events[2];
+        assert_eq!(program.name, Some("PP"));
+        assert_eq!(program.address, Some(0x1234));
+        assert_eq!((program.written, program.read), (3, 0));
+
+        let mut buff = [0; 3];
+        memory.read_fast(0x1234, &mut buff).await.unwrap();
+        let read = received.try_iter().last().unwrap();
+        assert_eq!(read.name, Some("FAST_READ"));
+        assert_eq!(read.address, Some(0x1234));
+        assert_eq!(read.dummy_bytes, 1);
+        assert_eq!((read.written, read.read), (0, 3));
+
+        let mut config = memory.read_configuration().await.unwrap();
+        let read = received.try_iter().last().unwrap();
+        assert_eq!(read.name, Some("RDCR"));
+        assert_eq!(read.configuration, Some(config));
+
+        config.power_mode = PowerMode::HighPerformance;
+        memory
+            .write_status_configuration(status, config)
+            .await
+            .unwrap();
+        let write = received.try_iter().last().unwrap();
+        assert_eq!(write.name, Some("WRSR"));
+        assert_eq!(write.written, 3);
+        assert_eq!(write.status, Some(status));
+        assert_eq!(write.configuration, Some(config));
+
+        memory.read_electronic_id().await.unwrap();
+        let read = received.try_iter().last().unwrap();
+        assert_eq!(read.name, Some("RES"));
+        assert_eq!(read.dummy_bytes, 3);
+        assert_eq!(read.read, 1);
+    })
+}
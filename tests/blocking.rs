@@ -0,0 +1,1100 @@
+//! The scenarios of `nrf52840/tests/blocking.rs` and the rest of the `MX25R` API, run on the simulator.
+
+use std::{io, sync::mpsc};
+
+use embedded_hal::spi::{ErrorType, Operation as SpiOperation, SpiDevice};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use mx25r::{
+    blocking::{DynamicMX25R, MX25R6435F},
+    bus::ReadMode,
+    erase::{ErasePlan, EraseStep},
+    error::Error,
+    part::Part,
+    power::PowerThresholds,
+    register::{BurstLength, PowerMode, ProtectedArea},
+    sfdp::SfdpHeader,
+    sim::{FileStorage, SimError, SimTimings, Simulator, Storage, VirtualClock},
+    timing::Operation,
+    trace::{Event, Trace},
+    BLOCK32_SIZE, BLOCK64_SIZE, DEEP_POWER_DOWN_RECOVERY_US, PAGE_SIZE, SECTOR_SIZE,
+    SECURE_OTP_SIZE,
+};
+
+type Flash = MX25R6435F<Simulator, VirtualClock>;
+
+const CAPACITY: u32 = 8 * 1024 * 1024;
+
+/// A blank chip with the typical durations of the datasheet
+fn memory() -> Flash {
+    memory_with(SimTimings::typical(Part::MX25R6435F))
+}
+
+fn memory_with(timings: SimTimings) -> Flash {
+    memory_and_clock(timings).0
+}
+
+/// The clock is shared by the simulator and the driver, a clone advances them both
+fn memory_and_clock(timings: SimTimings) -> (Flash, VirtualClock) {
+    let clock = VirtualClock::new();
+    let mut sim = Simulator::new(Part::MX25R6435F);
+    sim.set_timing(clock.clone(), timings);
+    (MX25R6435F::new(sim, clock.clone()), clock)
+}
+
+#[test]
+fn basic() {
+    let mut memory = memory();
+    let mut buff = [0];
+    let addr = 0;
+
+    memory.read(addr, &mut buff).unwrap();
+    memory
+        .erase_sector(addr)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+
+    memory.read(addr, &mut buff).unwrap();
+    assert_eq!(buff[0], 0xff);
+
+    memory
+        .write_page(0, &[42])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+
+    memory.read(addr, &mut buff).unwrap();
+    assert_eq!(buff[0], 42);
+
+    memory
+        .erase_sector(addr)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+}
+
+/// Read multiple bytes in a single call.
+#[test]
+fn read_multiple_bytes() {
+    let mut memory = memory();
+    const LEN: usize = 16;
+    let mut buf = [0u8; LEN];
+    memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
+
+    let mut pattern = [0u8; LEN];
+    for (i, e) in pattern.iter_mut().enumerate() {
+        *e = i as u8;
+    }
+    memory
+        .write_page(0, &pattern)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    memory.read(0, &mut buf).unwrap();
+    assert_eq!(&buf, &pattern);
+}
+
+/// Read spanning one sector end into the next.
+#[test]
+fn read_across_sector_boundary() {
+    let mut memory = memory();
+    let mut buf = [0u8; 16];
+
+    memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
+    memory
+        .write_page(SECTOR_SIZE, &[1; 8])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    memory.read(SECTOR_SIZE - 8, &mut buf).unwrap();
+    assert_eq!(
+        buf,
+        [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 1, 1, 1, 1, 1, 1, 1]
+    );
+}
+
+/// Out-of-bounds reads should error.
+#[test]
+fn read_out_of_bounds() {
+    let mut memory = memory();
+    let mut buf = [0u8; 16];
+    let res = memory.read(0x4000_0000, &mut buf);
+    assert!(matches!(res, Err(Error::OutOfBounds)));
+}
+
+/// Directly exercise the ReadNorFlash trait’s `read` method with an absolute offset.
+#[test]
+fn direct_trait_read() {
+    let mut memory = memory();
+    let mut buf = [0u8; 4];
+
+    memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
+    memory
+        .write_page(0, &[1, 2, 3, 4])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+
+    ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 4]);
+}
+
+/// Check that `capacity()` returns the expected total size.
+#[test]
+fn trait_capacity() {
+    let memory = memory();
+    assert_eq!(ReadNorFlash::capacity(&memory), CAPACITY as usize);
+}
+
+/// Write a blob at an arbitrary offset via the `NorFlash` & `ReadNorFlash` traits, then read it back.
+#[test]
+fn trait_write_read() {
+    let mut memory = memory();
+    const DATA: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
+    let mut buf = [0u8; 4];
+
+    NorFlash::erase(&mut memory, 0, SECTOR_SIZE).unwrap();
+    NorFlash::write(&mut memory, 0, &DATA).unwrap();
+    ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
+    assert_eq!(buf, DATA);
+}
+
+/// Erase a region that spans two sectors via the `NorFlash` and `ReadNorFlash` traits,
+/// then verify before/after.
+#[test]
+fn trait_erase_range() {
+    let mut memory = memory();
+    const LEN: usize = SECTOR_SIZE as usize + (SECTOR_SIZE / 2) as usize;
+    const ERASE: u32 = 2 * SECTOR_SIZE;
+    let mut buf = [0u8; LEN];
+    let data = [0x55u8; LEN];
+
+    NorFlash::erase(&mut memory, 0, ERASE).unwrap();
+
+    ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0xFF));
+
+    NorFlash::write(&mut memory, 0, &data).unwrap();
+
+    ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0x55));
+
+    NorFlash::erase(&mut memory, 0, ERASE).unwrap();
+
+    ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0xFF));
+}
+
+/// Writes crossing the pages are split by the `NorFlash` implementation
+#[test]
+fn trait_write_across_pages() {
+    let mut memory = memory();
+    let data: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| i as u8).collect();
+    let mut buf = vec![0; data.len()];
+
+    NorFlash::write(&mut memory, PAGE_SIZE / 2, &data).unwrap();
+    ReadNorFlash::read(&mut memory, PAGE_SIZE / 2, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert!(matches!(
+        NorFlash::erase(&mut memory, 0, SECTOR_SIZE / 2),
+        Err(Error::NotAligned)
+    ));
+}
+
+/// Decode the JEDEC Basic Flash Parameter Table and check it against the expected part.
+#[test]
+fn sfdp_basic_flash_parameters() {
+    let mut memory = memory();
+    let header = memory.read_sfdp_header().unwrap();
+    assert!(header.parameter_headers() >= 1);
+
+    let params = memory.read_basic_flash_parameters().unwrap();
+    assert_eq!(params.capacity(), CAPACITY as u64);
+    assert_eq!(params.erase_4k_opcode, Some(0x20));
+    assert_eq!(params.fast_read.read_1_4_4.unwrap().opcode, 0xEB);
+    assert_eq!(params.page_size, Some(256));
+}
+
+#[test]
+fn sfdp_raw() {
+    let mut memory = memory();
+    let mut signature = [0; 4];
+    memory.read_sfdp(0, &mut signature).unwrap();
+    assert_eq!(&signature, b"SFDP");
+
+    let header = memory.read_sfdp_parameter_header(0).unwrap();
+    assert!(header.is_basic_flash_parameters());
+    assert!(header.length >= 9);
+}
+
+/// The NPH field is zero based, its largest value announces 256 parameter headers.
+#[test]
+fn sfdp_parameter_headers_count() {
+    let mut bytes = [b'S', b'F', b'D', b'P', 0x06, 0x01, 0, 0xFF];
+    assert_eq!(
+        SfdpHeader::from_bytes(&bytes).unwrap().parameter_headers(),
+        1
+    );
+
+    bytes[6] = 0xFF;
+    let header = SfdpHeader::from_bytes(&bytes).unwrap();
+    assert_eq!(header.last_parameter_header, 0xFF);
+    assert_eq!(header.parameter_headers(), 256);
+}
+
+/// Protect the top of the array and check the reported range, then remove the protection.
+#[test]
+fn protect_top_range() {
+    let mut memory = memory();
+    // The smallest setting protects 1/64 of the array
+    let range = CAPACITY - CAPACITY / 64..CAPACITY;
+
+    memory.protect_range(range.clone()).unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(memory.protected_range().unwrap(), range);
+
+    memory.protect_range(0..0).unwrap();
+    memory.wait_wip().unwrap();
+    assert!(memory.protected_range().unwrap().is_empty());
+}
+
+/// Protect the smallest area covering a range, without touching the top/bottom bit.
+#[test]
+fn protect_covering_range() {
+    let mut memory = memory();
+
+    let protection = memory.protect_range(CAPACITY - 100..CAPACITY).unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(
+        memory.protected_range().unwrap(),
+        CAPACITY - CAPACITY / 64..CAPACITY
+    );
+    assert_eq!(protection.area, ProtectedArea::Top);
+
+    // Only the whole chip covers it from the top, the bottom must be selected
+    assert!(matches!(
+        memory.protect_range(0..CAPACITY / 64),
+        Err(Error::Value)
+    ));
+    assert_eq!(
+        memory.protected_range().unwrap(),
+        CAPACITY - CAPACITY / 64..CAPACITY
+    );
+    memory.protect_range(0..CAPACITY).unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(memory.protected_range().unwrap(), 0..CAPACITY);
+    assert_eq!(
+        memory.read_configuration().unwrap().protected_section,
+        ProtectedArea::Top
+    );
+
+    assert!(matches!(
+        memory.protect_range(0..CAPACITY + 1),
+        Err(Error::Value)
+    ));
+}
+
+#[test]
+fn protect_bottom_range() {
+    let mut memory = memory();
+    let range = 0..CAPACITY / 64;
+
+    let protection = memory
+        .protect_range_in(range.clone(), ProtectedArea::Bottom)
+        .unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(protection.area, ProtectedArea::Bottom);
+    assert_eq!(memory.read_block_protection().unwrap(), protection);
+    assert_eq!(memory.protected_range().unwrap(), range);
+    // The top/bottom bit is one time programmable
+    assert!(matches!(
+        memory.protect_range_in(CAPACITY - CAPACITY / 64..CAPACITY, ProtectedArea::Top),
+        Err(Error::Value)
+    ));
+}
+
+#[test]
+fn failure_detection() {
+    let mut memory = memory();
+    assert!(!memory.failure_detection());
+    memory.set_failure_detection(true);
+    assert!(memory.failure_detection());
+
+    let top = CAPACITY - SECTOR_SIZE;
+    memory
+        .protect_range(CAPACITY - CAPACITY / 64..CAPACITY)
+        .unwrap();
+    memory.wait_wip().unwrap();
+    assert!(matches!(
+        NorFlash::write(&mut memory, top, &[0]),
+        Err(Error::ProgramFailed { addr }) if addr == top
+    ));
+    assert!(matches!(
+        NorFlash::erase(&mut memory, top, CAPACITY),
+        Err(Error::EraseFailed { addr }) if addr == top
+    ));
+    NorFlash::write(&mut memory, 0, &[0]).unwrap();
+}
+
+/// The next program finds the failed one completed, both it and the token report the failure
+#[test]
+fn failure_reported_by_next_operation() {
+    let mut memory = memory();
+    memory.set_failure_detection(true);
+    let top = CAPACITY - SECTOR_SIZE;
+    memory
+        .protect_range(CAPACITY - CAPACITY / 64..CAPACITY)
+        .unwrap();
+    memory.wait_wip().unwrap();
+
+    let program = memory.write_page(top, &[0]).unwrap();
+    assert!(matches!(
+        memory.write_page(0, &[0]),
+        Err(Error::ProgramFailed { addr }) if addr == top
+    ));
+    assert!(matches!(
+        program.wait(&mut memory),
+        Err(Error::ProgramFailed { addr }) if addr == top
+    ));
+    memory
+        .write_page(0, &[0])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+}
+
+#[test]
+fn secure_otp_read() {
+    let mut memory = memory();
+    let mut array = [0; 16];
+    memory.read_fast(0, &mut array).unwrap();
+
+    let mut buff = [0; 16];
+    let mut otp = memory.secure_otp().unwrap();
+    otp.read(0, &mut buff).unwrap();
+    assert!(matches!(
+        otp.read(SECURE_OTP_SIZE - 8, &mut buff),
+        Err(Error::OutOfBounds)
+    ));
+    otp.exit().unwrap();
+
+    // Dropping the guard exits the OTP as well
+    let otp = memory.secure_otp().unwrap();
+    drop(otp);
+    memory.read_fast(0, &mut buff).unwrap();
+    assert_eq!(buff, array);
+}
+
+#[test]
+fn secure_otp_program_and_lock() {
+    let mut memory = memory();
+    let mut otp = memory.secure_otp().unwrap();
+    otp.program(PAGE_SIZE - 2, &[1, 2, 3, 4]).unwrap();
+    let mut buff = [0; 4];
+    otp.read(PAGE_SIZE - 2, &mut buff).unwrap();
+    assert_eq!(buff, [1, 2, 3, 4]);
+    otp.exit().unwrap();
+
+    // The array is untouched
+    memory.read(PAGE_SIZE - 2, &mut buff).unwrap();
+    assert_eq!(buff, [0xFF; 4]);
+
+    // The lock down waits for the program in progress, it needs the write enable sent once the chip is ready
+    let program = memory.write_page(0, &[0]).unwrap();
+    assert!(matches!(memory.write_security_register(), Err(Error::Busy)));
+    program.wait(&mut memory).unwrap();
+    assert!(!memory.read_security_register().unwrap().locked_down);
+
+    memory.write_security_register().unwrap();
+    memory.wait_wip().unwrap();
+    assert!(memory.read_security_register().unwrap().locked_down);
+
+    // The raw instructions, the lock down refuses the program
+    memory.enter_secure_opt().unwrap();
+    memory
+        .write_page(0, &[0])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    assert!(memory.read_security_register().unwrap().program_failed);
+    memory.read(0, &mut buff[..1]).unwrap();
+    assert_eq!(buff[0], 0xFF);
+    memory.exit_secure_opt().unwrap();
+}
+
+#[test]
+fn deep_power_down_wake() {
+    let (memory, clock) = memory_and_clock(SimTimings::typical(Part::MX25R6435F));
+    let sleeping = memory.deep_power_down().ok().unwrap();
+    let start = clock.now_us();
+    let mut memory = sleeping.wake().ok().unwrap();
+    assert!(clock.now_us() - start >= u64::from(DEEP_POWER_DOWN_RECOVERY_US));
+
+    let (manufacturer, _, _) = memory.read_identification().unwrap();
+    assert_eq!(manufacturer.0, 0xC2);
+}
+
+#[test]
+fn pending_operation_refuses_conflicts() {
+    let mut memory = memory();
+    let erase = memory.erase_sector(0).unwrap();
+    assert_eq!(erase.operation(), Operation::SectorErase);
+    assert_eq!(erase.addr(), 0);
+    assert!(matches!(memory.write_page(0, &[42]), Err(Error::Busy)));
+
+    while let Err(Error::Busy) = erase.poll(&mut memory) {}
+    erase.poll(&mut memory).unwrap();
+    memory
+        .write_page(0, &[42])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+}
+
+/// A token polled to completion is stale once another program or erase starts, it doesn't wait for it
+#[test]
+fn stale_token() {
+    let mut memory = memory();
+    let erase = memory.erase_sector(0).unwrap();
+    while let Err(Error::Busy) = erase.poll(&mut memory) {}
+
+    let program = memory.write_page(SECTOR_SIZE, &[42]).unwrap();
+    assert!(matches!(erase.poll(&mut memory), Err(Error::Value)));
+    assert!(matches!(erase.wait(&mut memory), Err(Error::Value)));
+    assert!(matches!(program.poll(&mut memory), Err(Error::Busy)));
+    program.wait(&mut memory).unwrap();
+}
+
+#[test]
+fn read_while_erasing() {
+    let mut memory = memory();
+    memory
+        .write_page(0, &[1; 16])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    let mut expected = [0; 16];
+    memory.read_fast(0, &mut expected).unwrap();
+
+    let mut buff = [0; 16];
+    let erase = memory.erase_block64(BLOCK64_SIZE).unwrap();
+    memory.read_while_erasing(0, &mut buff).unwrap();
+    assert_eq!(buff, expected);
+    assert!(matches!(
+        memory.read_while_erasing(BLOCK64_SIZE, &mut buff),
+        Err(Error::Busy)
+    ));
+    erase.wait(&mut memory).unwrap();
+}
+
+/// Fails the first transaction sending `opcode` with a bus error, the others reach the simulator
+struct FailOnce {
+    sim: Simulator,
+    opcode: Option<u8>,
+}
+
+impl ErrorType for FailOnce {
+    type Error = SimError;
+}
+
+impl SpiDevice for FailOnce {
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_, u8>]) -> Result<(), SimError> {
+        if let [SpiOperation::Write(header), ..] = operations {
+            if self
+                .opcode
+                .is_some_and(|opcode| header.first() == Some(&opcode))
+            {
+                self.opcode = None;
+                return Err(SimError::Bus);
+            }
+        }
+        self.sim.transaction(operations)
+    }
+}
+
+/// A bus error once the erase is suspended fails the read, the erase is resumed anyway
+#[test]
+fn read_while_erasing_resumes_on_error() {
+    let clock = VirtualClock::new();
+    let mut sim = Simulator::new(Part::MX25R6435F);
+    sim.set_timing(clock.clone(), SimTimings::typical(Part::MX25R6435F));
+    // The security register is first read once the erase is suspended
+    let bus = FailOnce {
+        sim,
+        opcode: Some(0x2B),
+    };
+    let mut memory = MX25R6435F::new(bus, clock);
+
+    let erase = memory.erase_block64(BLOCK64_SIZE).unwrap();
+    let mut buff = [0; 16];
+    assert!(matches!(
+        memory.read_while_erasing(0, &mut buff),
+        Err(Error::Spi(SimError::Bus))
+    ));
+    assert!(memory.read_status().unwrap().wip_bit);
+    assert!(!memory.read_security_register().unwrap().erase_suspended);
+    erase.wait(&mut memory).unwrap();
+    memory.read(BLOCK64_SIZE, &mut buff).unwrap();
+    assert_eq!(buff, [0xFF; 16]);
+}
+
+/// A program outlasting its limit is left in progress, the exit deferred by the dropped guard waits for it
+#[test]
+fn secure_otp_drop_during_program() {
+    let mut timings = SimTimings::max(Part::MX25R6435F);
+    timings.operations.page_program_us *= 3;
+    let mut memory = memory_with(timings);
+
+    let mut otp = memory.secure_otp().unwrap();
+    assert!(matches!(otp.program(0, &[24]), Err(Error::Timeout)));
+    // The exit on drop times out as well and is retried by the next instruction, the poll doesn't flush it
+    drop(otp);
+    assert!(matches!(memory.poll_wip(), Err(Error::Busy)));
+
+    let mut buff = [0];
+    memory.read(0, &mut buff).unwrap();
+    assert_eq!(buff, [0xFF]);
+    assert!(!memory.read_status().unwrap().wip_bit);
+
+    let mut otp = memory.secure_otp().unwrap();
+    otp.read(0, &mut buff).unwrap();
+    assert_eq!(buff, [24]);
+    otp.exit().unwrap();
+}
+
+#[test]
+fn suspend_resume() {
+    let mut memory = memory();
+    let erase = memory.erase_sector(0).unwrap();
+    memory.suspend_program_erase().unwrap();
+    memory.wait_wip().unwrap();
+    assert!(memory.read_security_register().unwrap().erase_suspended);
+
+    memory.resume_program_erase().unwrap();
+    assert!(memory.read_status().unwrap().wip_bit);
+    assert!(!memory.read_security_register().unwrap().erase_suspended);
+    // The resumed erase is pending again, the token waits for it
+    erase.wait(&mut memory).unwrap();
+    assert!(!memory.read_status().unwrap().wip_bit);
+}
+
+#[test]
+fn read_wrapped() {
+    let mut memory = memory();
+    let pattern: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+    memory.erase_sector(0).unwrap().wait(&mut memory).unwrap();
+    memory
+        .write_page(0, &pattern)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+
+    let mut buff = [0; 8];
+    assert!(matches!(
+        memory.read_wrapped(4, &mut buff),
+        Err(Error::Value)
+    ));
+    memory.set_burst_length(BurstLength::Wrap8).unwrap();
+    assert_eq!(memory.burst_length(), BurstLength::Wrap8);
+    memory.read_wrapped(4, &mut buff).unwrap();
+    assert_eq!(buff, [4, 5, 6, 7, 0, 1, 2, 3]);
+    memory.set_burst_length(BurstLength::Disabled).unwrap();
+}
+
+/// The chip ignores the burst length while busy, it's refused during an erase and sent once the chip is ready
+#[test]
+fn burst_length_while_busy() {
+    let mut memory = memory();
+    memory
+        .write_page(0, &[0, 1, 2, 3, 4, 5, 6, 7])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+
+    let erase = memory.erase_sector(SECTOR_SIZE * 16).unwrap();
+    assert!(matches!(
+        memory.set_burst_length(BurstLength::Wrap8),
+        Err(Error::Busy)
+    ));
+    assert_eq!(memory.burst_length(), BurstLength::Disabled);
+    erase.wait(&mut memory).unwrap();
+
+    // A register write without token is waited for
+    memory.modify_status(|_| {}).unwrap();
+    memory.set_burst_length(BurstLength::Wrap8).unwrap();
+    let mut buff = [0; 8];
+    memory.read_wrapped(4, &mut buff).unwrap();
+    assert_eq!(buff, [4, 5, 6, 7, 0, 1, 2, 3]);
+}
+
+/// The transfers over the thresholds switch to the high performance mode and back, as traced on the bus
+#[test]
+fn managed_power_mode() {
+    let (events, received) = mpsc::channel();
+    let bus = Trace::new(Simulator::new(Part::MX25R6435F), move |event: &Event| {
+        events.send(*event).unwrap()
+    });
+    let mut memory = MX25R6435F::new(bus, VirtualClock::new());
+    assert_eq!(memory.power_management(), None);
+    memory.set_power_mode(PowerMode::UltraLowPower).unwrap();
+    let thresholds = PowerThresholds {
+        read: 16,
+        write: 16,
+    };
+    memory.set_power_management(Some(thresholds));
+    assert_eq!(memory.power_management(), Some(thresholds));
+
+    let pattern = [0x5A; 32];
+    let mut buff = [0; 32];
+    NorFlash::erase(&mut memory, 0, SECTOR_SIZE).unwrap();
+    NorFlash::write(&mut memory, 0, &pattern).unwrap();
+    ReadNorFlash::read(&mut memory, 0, &mut buff).unwrap();
+    // Below the thresholds
+    ReadNorFlash::read(&mut memory, 0, &mut buff[..8]).unwrap();
+    assert_eq!(buff, pattern);
+    let modes: Vec<_> = received
+        .try_iter()
+        .filter(|event| event.name == Some("WRSR"))
+        .filter_map(|event| event.configuration.map(|config| config.power_mode))
+        .collect();
+    assert_eq!(
+        modes,
+        [
+            PowerMode::UltraLowPower,
+            PowerMode::HighPerformance,
+            PowerMode::UltraLowPower,
+            PowerMode::HighPerformance,
+            PowerMode::UltraLowPower,
+        ]
+    );
+    assert_eq!(memory.power_mode(), Some(PowerMode::UltraLowPower));
+    assert_eq!(
+        memory.read_configuration().unwrap().power_mode,
+        PowerMode::UltraLowPower
+    );
+}
+
+#[test]
+fn trait_erase_unaligned_blocks() {
+    let mut memory = memory();
+    const FROM: u32 = SECTOR_SIZE;
+    const TO: u32 = SECTOR_SIZE + BLOCK64_SIZE;
+
+    // 7 sectors up to the first 32K block, the 32K block and the last sector
+    let plan = ErasePlan::new(FROM, TO).unwrap();
+    assert_eq!(plan.count(), 9);
+
+    NorFlash::write(&mut memory, 0, &[0; 32]).unwrap();
+    NorFlash::write(&mut memory, TO - 16, &[0; 32]).unwrap();
+    NorFlash::erase(&mut memory, FROM, TO).unwrap();
+    let mut buf = [0u8; 16];
+    ReadNorFlash::read(&mut memory, TO - 16, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0xFF));
+    // Outside of the range
+    ReadNorFlash::read(&mut memory, 0, &mut buf).unwrap();
+    assert_eq!(buf, [0; 16]);
+    ReadNorFlash::read(&mut memory, TO, &mut buf).unwrap();
+    assert_eq!(buf, [0; 16]);
+}
+
+#[test]
+fn erase_alignment() {
+    let mut memory = memory();
+    assert!(matches!(memory.erase_sector(1), Err(Error::NotAligned)));
+    assert!(matches!(
+        memory.erase_block32(SECTOR_SIZE),
+        Err(Error::NotAligned)
+    ));
+    assert!(matches!(
+        memory.erase_block64(BLOCK32_SIZE),
+        Err(Error::NotAligned)
+    ));
+    assert!(matches!(
+        memory.erase_block64(CAPACITY),
+        Err(Error::OutOfBounds)
+    ));
+
+    NorFlash::write(&mut memory, BLOCK32_SIZE, &[0; 4]).unwrap();
+    memory
+        .erase_block32(BLOCK32_SIZE)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    let op = memory.erase_step(EraseStep::Block64(0)).unwrap();
+    assert_eq!(op.operation(), Operation::Block64Erase);
+    op.wait(&mut memory).unwrap();
+    let mut buf = [0; 4];
+    memory.read(BLOCK32_SIZE, &mut buf).unwrap();
+    assert_eq!(buf, [0xFF; 4]);
+}
+
+#[test]
+fn erase_chip() {
+    let mut memory = memory();
+    NorFlash::write(&mut memory, CAPACITY - 4, &[0; 4]).unwrap();
+    let erase = memory.erase_chip().unwrap();
+    assert_eq!(erase.operation(), Operation::ChipErase);
+    erase.wait(&mut memory).unwrap();
+    let mut buf = [0; 4];
+    memory.read(CAPACITY - 4, &mut buf).unwrap();
+    assert_eq!(buf, [0xFF; 4]);
+}
+
+/// The polls of the long operations are at most 100ms apart, the wait ends soon after the chip is ready
+#[test]
+fn wait_interval_capped() {
+    let (mut memory, clock) = memory_and_clock(SimTimings::typical(Part::MX25R6435F));
+    memory.erase_chip().unwrap().wait(&mut memory).unwrap();
+    let typical = u64::from(Part::MX25R6435F.typical_timings().chip_erase_us);
+    assert!((typical..typical + 100_100).contains(&clock.now_us()));
+}
+
+#[test]
+fn properties() {
+    let memory = memory();
+    assert_eq!(memory.capacity(), CAPACITY as usize);
+    assert_eq!(memory.part(), Some(Part::MX25R6435F));
+    assert_eq!(memory.timings(), Part::MX25R6435F.max_timings());
+    assert_eq!(memory.typical_timings(), Part::MX25R6435F.typical_timings());
+    assert_eq!(memory.verify_addr(CAPACITY - 1).unwrap(), CAPACITY - 1);
+    assert!(matches!(
+        memory.verify_addr(CAPACITY),
+        Err(Error::OutOfBounds)
+    ));
+}
+
+#[test]
+fn read_modes() {
+    let mut memory = memory();
+    NorFlash::write(&mut memory, 0, &[1, 2, 3, 4]).unwrap();
+
+    let mut normal = [0; 4];
+    let mut fast = [0; 4];
+    memory.read(0, &mut normal).unwrap();
+    memory.read_fast(0, &mut fast).unwrap();
+    assert_eq!(normal, [1, 2, 3, 4]);
+    assert_eq!(fast, normal);
+
+    // A SPI device only has a single data line
+    let mut buff = [0; 4];
+    assert!(matches!(
+        memory.read_dual_output(0, &mut buff),
+        Err(Error::Unsupported)
+    ));
+    assert!(matches!(
+        memory.read_dual_io(0, &mut buff),
+        Err(Error::Unsupported)
+    ));
+    assert!(matches!(
+        memory.read_quad_output(0, &mut buff),
+        Err(Error::Unsupported)
+    ));
+    assert!(matches!(
+        memory.read_quad_io(0, &mut buff),
+        Err(Error::Unsupported)
+    ));
+    assert!(matches!(
+        memory.write_page_quad(0, &[0]),
+        Err(Error::Unsupported)
+    ));
+
+    assert_eq!(memory.read_mode(), ReadMode::Fast);
+    assert!(matches!(
+        memory.set_read_mode(ReadMode::QuadIo),
+        Err(Error::Unsupported)
+    ));
+    memory.set_read_mode(ReadMode::Normal).unwrap();
+    assert_eq!(memory.read_mode(), ReadMode::Normal);
+    memory.read_with_mode(0, &mut buff).unwrap();
+    assert_eq!(buff, [1, 2, 3, 4]);
+}
+
+#[test]
+fn registers() {
+    let mut memory = memory();
+    memory.write_disable().unwrap();
+    assert!(!memory.read_status().unwrap().write_enable_latch);
+
+    let status = memory
+        .modify_status(|status| status.protected_block = 1)
+        .unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(memory.read_status().unwrap(), status);
+
+    let config = memory
+        .modify_configuration(|config| config.dummmy_cycle = true)
+        .unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(memory.read_configuration().unwrap(), config);
+
+    let mut status = memory.read_status().unwrap();
+    status.protected_block = 0;
+    memory.write_status(status).unwrap();
+    memory.wait_wip().unwrap();
+    assert!(memory.protected_range().unwrap().is_empty());
+
+    status.protected_block = 0x10;
+    assert!(matches!(memory.write_status(status), Err(Error::Value)));
+
+    let mut config = memory.read_configuration().unwrap();
+    config.power_mode = PowerMode::HighPerformance;
+    status.protected_block = 0;
+    memory.write_status_configuration(status, config).unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(memory.read_configuration().unwrap(), config);
+
+    #[allow(deprecated)]
+    memory
+        .write_configuration(
+            0,
+            false,
+            false,
+            false,
+            ProtectedArea::Top,
+            PowerMode::UltraLowPower,
+        )
+        .unwrap();
+    memory.wait_wip().unwrap();
+    assert_eq!(
+        memory.read_configuration().unwrap().power_mode,
+        PowerMode::UltraLowPower
+    );
+}
+
+#[test]
+fn identification() {
+    let mut memory = memory();
+    let (manufacturer, memory_type, density) = memory.read_identification().unwrap();
+    assert_eq!(
+        Part::from_identification(manufacturer, memory_type, density),
+        Some(Part::MX25R6435F)
+    );
+    assert_eq!(memory.read_electronic_id().unwrap().0, density.0);
+    let (manufacturer, device) = memory.read_manufacturer_id().unwrap();
+    assert_eq!(manufacturer.0, 0xC2);
+    assert_eq!(device.0, density.0);
+}
+
+#[test]
+fn reset() {
+    let mut memory = memory();
+    memory.nop().unwrap();
+    memory.set_burst_length(BurstLength::Wrap16).unwrap();
+
+    // A lone reset enable is cancelled by the next instruction
+    memory.reset_enable().unwrap();
+    memory.nop().unwrap();
+    memory.reset().unwrap();
+    assert_eq!(memory.burst_length(), BurstLength::Disabled);
+
+    // The wraps are disabled on the chip as well
+    NorFlash::write(&mut memory, 0, &[1; 16]).unwrap();
+    let mut buff = [0; 32];
+    memory.read(0, &mut buff).unwrap();
+    assert_eq!(buff[..16], [1; 16]);
+    assert_eq!(buff[16..], [0xFF; 16]);
+}
+
+#[test]
+fn detect() {
+    let clock = VirtualClock::new();
+    let mut sim = Simulator::new(Part::MX25R1635F);
+    sim.set_timing(clock.clone(), SimTimings::typical(Part::MX25R1635F));
+    let mut memory = DynamicMX25R::detect(sim, clock).unwrap();
+    assert_eq!(memory.part(), Some(Part::MX25R1635F));
+    assert_eq!(memory.capacity(), Part::MX25R1635F.capacity());
+
+    let last = memory.capacity() as u32 - 4;
+    NorFlash::write(&mut memory, last, &[1, 2, 3, 4]).unwrap();
+    let mut buff = [0; 4];
+    memory.read(last, &mut buff).unwrap();
+    assert_eq!(buff, [1, 2, 3, 4]);
+    assert!(matches!(
+        memory.read(last + 4, &mut buff),
+        Err(Error::OutOfBounds)
+    ));
+
+    let memory = DynamicMX25R::new_with_part(
+        Simulator::new(Part::MX25R512F),
+        VirtualClock::new(),
+        Part::MX25R512F,
+    );
+    assert_eq!(memory.capacity(), Part::MX25R512F.capacity());
+}
+
+/// An image created from a base keeps its writes once reopened, the base is then not needed anymore.
+/// The page buffer of the chip keeps the last page size bytes of a longer program
+#[test]
+fn program_longer_than_page() {
+    let mut memory = memory();
+    let mut data = [0x00; 44 + PAGE_SIZE as usize];
+    data[44..].fill(0xFF);
+    memory
+        .write_page(0, &data)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    let mut page = [0; PAGE_SIZE as usize];
+    memory.read(0, &mut page).unwrap();
+    assert_eq!(page, [0xFF; PAGE_SIZE as usize]);
+
+    // The kept bytes wrap around the page from where the first of them falls
+    let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    memory
+        .write_page(PAGE_SIZE + 16, &data)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    memory.read(PAGE_SIZE, &mut page).unwrap();
+    let mut expected = [0; PAGE_SIZE as usize];
+    for (i, &byte) in data[300 - PAGE_SIZE as usize..].iter().enumerate() {
+        expected[(16 + 300 - PAGE_SIZE as usize + i) % PAGE_SIZE as usize] = byte;
+    }
+    assert_eq!(page, expected);
+}
+
+#[test]
+fn file_storage_reopen() {
+    const PART: Part = Part::MX25R512F;
+    let dir = std::env::temp_dir().join(format!("mx25r-reopen-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (image, base) = (dir.join("image.bin"), dir.join("base.bin"));
+    std::fs::write(&base, [0x5A; 16]).unwrap();
+
+    let storage = FileStorage::open_with_base(&image, &base, PART.capacity()).unwrap();
+    let sim = Simulator::with_storage(PART, storage);
+    let mut memory = DynamicMX25R::new_with_part(sim, VirtualClock::new(), PART);
+    NorFlash::write(&mut memory, 16, &[1, 2, 3, 4]).unwrap();
+    drop(memory);
+
+    std::fs::remove_file(&base).unwrap();
+    let storage = FileStorage::open_with_base(&image, &base, PART.capacity()).unwrap();
+    assert_eq!(storage.data()[..16], [0x5A; 16]);
+    assert_eq!(storage.data()[16..21], [1, 2, 3, 4, 0xFF]);
+
+    // An image of another size isn't resized
+    let err = FileStorage::open(&image, Part::MX25R1635F.capacity()).err();
+    assert_eq!(err.map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn wait_for_datasheet_durations() {
+    let (mut memory, clock) = memory_and_clock(SimTimings::typical(Part::MX25R6435F));
+
+    let program = memory.write_page(0, &[0]).unwrap();
+    assert!(matches!(program.poll(&mut memory), Err(Error::Busy)));
+    assert!(matches!(memory.poll_wip(), Err(Error::Busy)));
+    program.wait(&mut memory).unwrap();
+    memory.poll_wip().unwrap();
+    let typical = u64::from(Part::MX25R6435F.typical_timings().page_program_us);
+    assert!((typical..typical + 100).contains(&clock.now_us()));
+}
+
+#[test]
+fn timeout() {
+    let mut timings = SimTimings::max(Part::MX25R6435F);
+    timings.operations.sector_erase_us *= 2;
+    let mut memory = memory_with(timings);
+
+    let erase = memory.erase_sector(0).unwrap();
+    assert!(matches!(erase.wait(&mut memory), Err(Error::Timeout)));
+    // The chip completes eventually
+    memory.wait_wip().unwrap();
+}
+
+#[test]
+fn trace() {
+    let (events, received) = mpsc::channel();
+    let bus = Trace::new(Simulator::new(Part::MX25R6435F), move |event: &Event| {
+        events.send(*event).unwrap()
+    });
+    let mut memory = MX25R6435F::new(bus, VirtualClock::new());
+
+    let status = memory.read_status().unwrap();
+    let event = received.try_recv().unwrap();
+    assert_eq!(event.opcode, 0x05);
+    assert_eq!(event.name, Some("RDSR"));
+    assert_eq!(event.address, None);
+    assert_eq!((event.written, event.read), (0, 1));
+    assert_eq!(event.status, Some(status));
+    assert!(!event.failed);
+
+    memory
+        .write_page(0x1234, &[1, 2, 3])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    let events: Vec<_> = received.try_iter().collect();
+    let names: Vec<_> = events.iter().filter_map(|event| event.name).collect();
+    assert!(names.starts_with(&["RDSR", "WREN", "PP"]));
+    let program = events[2];
+    assert_eq!(program.name, Some("PP"));
+    assert_eq!(program.address, Some(0x1234));
+    assert_eq!((program.written, program.read), (3, 0));
+
+    let mut buff = [0; 3];
+    memory.read_fast(0x1234, &mut buff).unwrap();
+    let read = received.try_iter().last().unwrap();
+    assert_eq!(read.name, Some("FAST_READ"));
+    assert_eq!(read.address, Some(0x1234));
+    assert_eq!(read.dummy_bytes, 1);
+    assert_eq!((read.written, read.read), (0, 3));
+
+    let mut config = memory.read_configuration().unwrap();
+    let read = received.try_iter().last().unwrap();
+    assert_eq!(read.name, Some("RDCR"));
+    assert_eq!(read.configuration, Some(config));
+
+    config.power_mode = PowerMode::HighPerformance;
+    memory.write_status_configuration(status, config).unwrap();
+    let write = received.try_iter().last().unwrap();
+    assert_eq!(write.name, Some("WRSR"));
+    assert_eq!(write.written, 3);
+    assert_eq!(write.status, Some(status));
+    assert_eq!(write.configuration, Some(config));
+
+    memory.read_electronic_id().unwrap();
+    let read = received.try_iter().last().unwrap();
+    assert_eq!(read.name, Some("RES"));
+    assert_eq!(read.dummy_bytes, 3);
+    assert_eq!(read.read, 1);
+}
+
+#[test]
+fn display() {
+    let mut memory = memory();
+    let status = memory.read_status().unwrap();
+    assert_eq!(status.to_string(), "SRWD=0 QE=0 BP=0x0 WEL=0 WIP=0");
+    let config = memory.read_configuration().unwrap();
+    assert_eq!(config.to_string(), "DC=0 TB=top L/H=ultra low power");
+    let security = memory.read_security_register().unwrap();
+    assert_eq!(
+        security.to_string(),
+        "E_FAIL=0 P_FAIL=0 ESB=0 PSB=0 LDSO=0 SOTP=0"
+    );
+    assert_eq!(BurstLength::Wrap16.to_string(), "wrap around 16 bytes");
+    assert_eq!(memory.read_electronic_id().unwrap().to_string(), "0x17");
+
+    assert_eq!(
+        Error::<()>::ProgramFailed { addr: 0x1234 }.to_string(),
+        "program failed at 0x001234"
+    );
+    assert_eq!(Error::Spi("nack").to_string(), "SPI error: \"nack\"");
+
+    let (events, received) = mpsc::channel();
+    let bus = Trace::new(Simulator::new(Part::MX25R6435F), move |event: &Event| {
+        events.send(event.to_string()).unwrap()
+    });
+    let mut memory = MX25R6435F::new(bus, VirtualClock::new());
+    memory.read_fast(0x1234, &mut [0; 3]).unwrap();
+    assert_eq!(
+        received.try_iter().last().unwrap(),
+        "FAST_READ at 0x001234, 1 dummy, 3 read"
+    );
+    memory.read_status().unwrap();
+    assert_eq!(
+        received.try_recv().unwrap(),
+        "RDSR, 1 read, status SRWD=0 QE=0 BP=0x0 WEL=0 WIP=0"
+    );
+}
@@ -0,0 +1,309 @@
+//! The faults injected by the simulator, as the driver reports them and as they are left in the array.
+
+use std::{cell::RefCell, rc::Rc};
+
+use embedded_hal::spi::{ErrorType, Operation as SpiOperation, SpiDevice};
+use mx25r::{
+    blocking::MX25R6435F,
+    error::Error,
+    part::Part,
+    sim::{FaultConfig, SimError, Simulator, VirtualClock},
+    PAGE_SIZE, SECTOR_SIZE,
+};
+
+type Flash = MX25R6435F<Shared, VirtualClock>;
+
+/// The simulator is shared with the test, to change the faults and cycle the power behind the driver
+#[derive(Clone)]
+struct Shared(Rc<RefCell<Simulator>>);
+
+impl Shared {
+    fn set_faults(&self, config: Option<FaultConfig>) {
+        self.0.borrow_mut().set_faults(config);
+    }
+
+    fn is_powered(&self) -> bool {
+        self.0.borrow().is_powered()
+    }
+
+    fn power_cycle(&self) {
+        self.0.borrow_mut().power_cycle();
+    }
+}
+
+impl ErrorType for Shared {
+    type Error = SimError;
+}
+
+impl SpiDevice for Shared {
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_, u8>]) -> Result<(), SimError> {
+        self.0.borrow_mut().transaction(operations)
+    }
+}
+
+/// A blank chip completing the operations instantly, so that only the faults fail them
+fn memory() -> (Flash, Shared) {
+    let sim = Shared(Rc::new(RefCell::new(Simulator::new(Part::MX25R6435F))));
+    (MX25R6435F::new(sim.clone(), VirtualClock::new()), sim)
+}
+
+/// The power is back, the driver starts over as after a reboot
+fn reboot(sim: &Shared) -> Flash {
+    sim.power_cycle();
+    MX25R6435F::new(sim.clone(), VirtualClock::new())
+}
+
+/// Bytes fully changed to `done`, then at most one byte in between, the rest is left as it was
+fn interrupted(content: &[u8], done: u8, before: u8) -> usize {
+    let count = content.iter().take_while(|&&byte| byte == done).count();
+    let rest = content.get(count + 1..).unwrap_or_default();
+    assert!(rest.iter().all(|&byte| byte == before), "{content:02X?}");
+    count
+}
+
+/// The power is cut at each transaction in turn, a program is left partially done and the chip needs a power cycle
+#[test]
+fn power_loss_after() {
+    let mut partial = false;
+    for after in 1.. {
+        let (mut memory, sim) = memory();
+        sim.set_faults(Some(FaultConfig {
+            seed: after,
+            power_loss_after: Some(after),
+            ..FaultConfig::default()
+        }));
+        let res = memory
+            .write_page(0, &[0; PAGE_SIZE as usize])
+            .and_then(|program| program.wait(&mut memory));
+        if res.is_ok() {
+            assert!(sim.is_powered());
+            break;
+        }
+        assert!(matches!(res, Err(Error::Spi(SimError::PowerLoss))));
+        assert!(!sim.is_powered());
+        assert!(matches!(
+            memory.read_status(),
+            Err(Error::Spi(SimError::PowerLoss))
+        ));
+
+        let mut memory = reboot(&sim);
+        let mut page = [0; PAGE_SIZE as usize];
+        memory.read(0, &mut page).unwrap();
+        let done = interrupted(&page, 0x00, 0xFF);
+        partial |= done > 0 && done < page.len();
+        // Nothing is reported after a power loss
+        let security = memory.read_security_register().unwrap();
+        assert!(!security.program_failed);
+    }
+    assert!(partial);
+}
+
+/// An erase interrupted by a power loss leaves the start of the sector erased
+#[test]
+fn power_loss_probability() {
+    let (mut memory, sim) = memory();
+    for addr in (0..SECTOR_SIZE).step_by(PAGE_SIZE as usize) {
+        memory
+            .write_page(addr, &[0; PAGE_SIZE as usize])
+            .unwrap()
+            .wait(&mut memory)
+            .unwrap();
+    }
+    sim.set_faults(Some(FaultConfig {
+        seed: 1,
+        power_loss_probability: 1.0,
+        ..FaultConfig::default()
+    }));
+    // The reads aren't interrupted
+    let mut sector = vec![0; SECTOR_SIZE as usize];
+    memory.read(0, &mut sector).unwrap();
+    assert!(matches!(
+        memory.erase_sector(0),
+        Err(Error::Spi(SimError::PowerLoss))
+    ));
+    assert!(!sim.is_powered());
+
+    let mut memory = reboot(&sim);
+    sim.set_faults(None);
+    memory.read(0, &mut sector).unwrap();
+    let done = interrupted(&sector, 0xFF, 0x00);
+    assert!(done > 0 && done < sector.len());
+    assert!(!memory.read_security_register().unwrap().erase_failed);
+}
+
+/// The failures set P_FAIL and E_FAIL, reported by the driver when the detection is enabled
+#[test]
+fn program_erase_failures() {
+    let (mut memory, sim) = memory();
+    memory.set_failure_detection(true);
+    sim.set_faults(Some(FaultConfig {
+        seed: 2,
+        program_failure_probability: 1.0,
+        erase_failure_probability: 1.0,
+        ..FaultConfig::default()
+    }));
+
+    let addr = SECTOR_SIZE;
+    let program = memory.write_page(addr, &[0; PAGE_SIZE as usize]).unwrap();
+    assert!(matches!(
+        program.wait(&mut memory),
+        Err(Error::ProgramFailed { addr: failed }) if failed == addr
+    ));
+    assert!(sim.is_powered());
+    let mut page = [0; PAGE_SIZE as usize];
+    memory.read(addr, &mut page).unwrap();
+    interrupted(&page, 0x00, 0xFF);
+
+    sim.set_faults(Some(FaultConfig {
+        seed: 2,
+        erase_failure_probability: 1.0,
+        ..FaultConfig::default()
+    }));
+    let erase = memory.erase_sector(addr).unwrap();
+    assert!(matches!(
+        erase.wait(&mut memory),
+        Err(Error::EraseFailed { addr: failed }) if failed == addr
+    ));
+
+    // The flags are cleared by the next program or erase
+    sim.set_faults(None);
+    memory
+        .erase_sector(addr)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    memory.read(addr, &mut page).unwrap();
+    assert_eq!(page, [0xFF; PAGE_SIZE as usize]);
+    let security = memory.read_security_register().unwrap();
+    assert!(!security.program_failed && !security.erase_failed);
+}
+
+/// The bus errors fail the transaction without reaching the chip
+#[test]
+fn bus_errors() {
+    let (mut memory, sim) = memory();
+    sim.set_faults(Some(FaultConfig {
+        seed: 3,
+        bus_error_probability: 1.0,
+        ..FaultConfig::default()
+    }));
+    assert!(matches!(
+        memory.write_page(0, &[0]),
+        Err(Error::Spi(SimError::Bus))
+    ));
+    assert!(sim.is_powered());
+
+    sim.set_faults(Some(FaultConfig {
+        seed: 3,
+        bus_error_probability: 0.5,
+        ..FaultConfig::default()
+    }));
+    let failed = (0..100)
+        .filter(|_| matches!(memory.read_status(), Err(Error::Spi(SimError::Bus))))
+        .count();
+    assert!((20..80).contains(&failed));
+
+    sim.set_faults(None);
+    let mut buff = [0];
+    memory.read(0, &mut buff).unwrap();
+    assert_eq!(buff, [0xFF]);
+}
+
+/// A bit of the bytes read is flipped, the array is left untouched
+#[test]
+fn read_bit_flips() {
+    let (mut memory, sim) = memory();
+    let data: Vec<u8> = (0..PAGE_SIZE).map(|i| i as u8).collect();
+    memory
+        .write_page(0, &data)
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    sim.set_faults(Some(FaultConfig {
+        seed: 4,
+        read_bit_flip_probability: 1.0,
+        ..FaultConfig::default()
+    }));
+    let mut page = [0; PAGE_SIZE as usize];
+    memory.read(0, &mut page).unwrap();
+    for (read, written) in page.iter().zip(&data) {
+        assert_eq!((read ^ written).count_ones(), 1);
+    }
+
+    sim.set_faults(None);
+    memory.read(0, &mut page).unwrap();
+    assert_eq!(page[..], data[..]);
+}
+
+/// The power cycle restores the power and loses the volatile state, here the secure OTP mode
+#[test]
+fn power_cycle() {
+    let (mut memory, sim) = memory();
+    memory
+        .write_page(0, &[0x11])
+        .unwrap()
+        .wait(&mut memory)
+        .unwrap();
+    sim.set_faults(Some(FaultConfig {
+        seed: 5,
+        power_loss_after: Some(2),
+        ..FaultConfig::default()
+    }));
+    memory.enter_secure_opt().unwrap();
+    let mut buff = [0];
+    assert!(matches!(
+        memory.read(0, &mut buff),
+        Err(Error::Spi(SimError::PowerLoss))
+    ));
+    assert!(matches!(
+        memory.read(0, &mut buff),
+        Err(Error::Spi(SimError::PowerLoss))
+    ));
+
+    sim.power_cycle();
+    assert!(sim.is_powered());
+    memory.read(0, &mut buff).unwrap();
+    assert_eq!(buff, [0x11]);
+}
+
+/// Programs and erases with every fault enabled, the outcome of each step
+fn faulty_run(seed: u64) -> Vec<(bool, [u8; 16])> {
+    let (mut memory, sim) = memory();
+    memory.set_failure_detection(true);
+    sim.set_faults(Some(FaultConfig {
+        seed,
+        power_loss_probability: 0.1,
+        program_failure_probability: 0.2,
+        erase_failure_probability: 0.2,
+        read_bit_flip_probability: 0.05,
+        bus_error_probability: 0.1,
+        ..FaultConfig::default()
+    }));
+    let mut steps = Vec::new();
+    for i in 0..64 {
+        let addr = (i % 4) * SECTOR_SIZE;
+        let res = if i % 8 == 7 {
+            memory.erase_sector(addr)
+        } else {
+            memory.write_page(addr + i * 16, &[i as u8; 16])
+        }
+        .and_then(|operation| operation.wait(&mut memory));
+        if !sim.is_powered() {
+            memory = reboot(&sim);
+            memory.set_failure_detection(true);
+        }
+        let mut buff = [0; 16];
+        let read = memory.read(addr + i * 16, &mut buff);
+        steps.push((res.is_ok() && read.is_ok(), buff));
+    }
+    steps
+}
+
+/// The same seed draws the same faults, so a failing run can be replayed
+#[test]
+fn seeded_faults() {
+    let run = faulty_run(6);
+    assert!(run.iter().any(|(ok, _)| !ok));
+    assert_eq!(run, faulty_run(6));
+    assert_ne!(run, faulty_run(7));
+}
@@ -0,0 +1,217 @@
+//! Random sequences of operations sent through the `NorFlash` traits to the drivers on the simulator and to a reference
+//! model of a NOR flash, the results are compared after each step.
+
+use embassy_futures::block_on;
+use embedded_storage::nor_flash::{
+    self, ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use embedded_storage_async::nor_flash as async_nor_flash;
+use mx25r::{
+    asynchronous::AsyncDynamicMX25R,
+    blocking::DynamicMX25R,
+    part::Part,
+    poll::Backoff,
+    sim::{Simulator, VirtualClock},
+    PAGE_SIZE, SECTOR_SIZE,
+};
+use proptest::prelude::*;
+
+/// The smallest part keeps the full comparisons cheap
+const PART: Part = Part::MX25R512F;
+const CAPACITY: u32 = PART.capacity() as u32;
+/// Most of the operations target the first sectors, so that they overlap
+const HOT_SIZE: u32 = 4 * SECTOR_SIZE;
+
+/// What a NOR flash does: the programs only clear bits and the erases set a whole sector back to 0xFF
+struct Model {
+    memory: Vec<u8>,
+}
+
+impl Model {
+    fn new() -> Self {
+        Model {
+            memory: vec![0xFF; CAPACITY as usize],
+        }
+    }
+}
+
+impl ErrorType for Model {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for Model {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        nor_flash::check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+}
+
+impl NorFlash for Model {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        nor_flash::check_erase(self, from, to)?;
+        self.memory[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        nor_flash::check_write(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        for (cell, byte) in self.memory[offset..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+impl MultiwriteNorFlash for Model {}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Read { offset: u32, len: usize },
+    Write { offset: u32, data: Vec<u8> },
+    Erase { from: u32, to: u32 },
+}
+
+impl Op {
+    /// The range to compare once the operation is done
+    fn range(&self) -> (u32, usize) {
+        match self {
+            Op::Read { offset, len } => (*offset, *len),
+            Op::Write { offset, data } => (*offset, data.len()),
+            Op::Erase { from, to } => (*from, to.saturating_sub(*from) as usize),
+        }
+    }
+}
+
+/// Mostly in the first sectors and around the page boundaries, sometimes anywhere or past the end
+fn offset() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        4 => 0..HOT_SIZE,
+        3 => (0..HOT_SIZE / PAGE_SIZE, -8i32..8).prop_map(|(page, delta)| {
+            (page * PAGE_SIZE).saturating_add_signed(delta)
+        }),
+        1 => 0..CAPACITY + PAGE_SIZE,
+    ]
+}
+
+/// Mostly sector aligned, sometimes misaligned, reversed or past the end
+fn erase_bound() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        8 => (0..=HOT_SIZE / SECTOR_SIZE).prop_map(|sector| sector * SECTOR_SIZE),
+        1 => (0..=CAPACITY / SECTOR_SIZE + 1).prop_map(|sector| sector * SECTOR_SIZE),
+        1 => 0..HOT_SIZE,
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (offset(), 0..2 * PAGE_SIZE as usize).prop_map(|(offset, len)| Op::Read { offset, len }),
+        4 => (offset(), prop::collection::vec(any::<u8>(), 0..2 * PAGE_SIZE as usize + 2))
+            .prop_map(|(offset, data)| Op::Write { offset, data }),
+        2 => (erase_bound(), erase_bound()).prop_map(|(from, to)| Op::Erase { from, to }),
+    ]
+}
+
+fn ops() -> impl Strategy<Value = Vec<Op>> {
+    prop::collection::vec(op(), 1..32)
+}
+
+fn kind<E: NorFlashError>(res: Result<(), E>) -> Result<(), NorFlashErrorKind> {
+    res.map_err(|e| e.kind())
+}
+
+/// Run `op` on both flashes and compare the results, then the range touched
+fn step<F: MultiwriteNorFlash>(
+    flash: &mut F,
+    model: &mut Model,
+    op: &Op,
+) -> Result<(), TestCaseError> {
+    let (expected, res) = match op {
+        Op::Read { .. } => (Ok(()), Ok(())),
+        Op::Write { offset, data } => {
+            (model.write(*offset, data), kind(flash.write(*offset, data)))
+        }
+        Op::Erase { from, to } => (model.erase(*from, *to), kind(flash.erase(*from, *to))),
+    };
+    prop_assert_eq!(res, expected);
+
+    let (offset, len) = op.range();
+    let mut expected = vec![0; len];
+    let mut bytes = vec![0; len];
+    let expected_res = model.read(offset, &mut expected);
+    prop_assert_eq!(kind(flash.read(offset, &mut bytes)), expected_res);
+    prop_assert_eq!(bytes, expected);
+    Ok(())
+}
+
+/// Same as [`step`] through the async traits
+async fn step_async<F: async_nor_flash::MultiwriteNorFlash>(
+    flash: &mut F,
+    model: &mut Model,
+    op: &Op,
+) -> Result<(), TestCaseError> {
+    let (expected, res) = match op {
+        Op::Read { .. } => (Ok(()), Ok(())),
+        Op::Write { offset, data } => (
+            model.write(*offset, data),
+            kind(flash.write(*offset, data).await),
+        ),
+        Op::Erase { from, to } => (model.erase(*from, *to), kind(flash.erase(*from, *to).await)),
+    };
+    prop_assert_eq!(res, expected);
+
+    let (offset, len) = op.range();
+    let mut expected = vec![0; len];
+    let mut bytes = vec![0; len];
+    let expected_res = model.read(offset, &mut expected);
+    prop_assert_eq!(kind(flash.read(offset, &mut bytes).await), expected_res);
+    prop_assert_eq!(bytes, expected);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn blocking_matches_model(ops in ops()) {
+        let mut flash = DynamicMX25R::new_with_part(Simulator::new(PART), VirtualClock::new(), PART);
+        let mut model = Model::new();
+        for op in &ops {
+            step(&mut flash, &mut model, op)?;
+        }
+
+        let mut bytes = vec![0; CAPACITY as usize];
+        flash.read(0, &mut bytes).unwrap();
+        prop_assert!(bytes == model.memory);
+    }
+
+    #[test]
+    fn async_matches_model(ops in ops()) {
+        block_on(async {
+            let mut flash = AsyncDynamicMX25R::new_with_part(
+                Simulator::new(PART),
+                VirtualClock::new(),
+                Backoff::default(),
+                PART,
+            );
+            let mut model = Model::new();
+            for op in &ops {
+                step_async(&mut flash, &mut model, op).await?;
+            }
+
+            let mut bytes = vec![0; CAPACITY as usize];
+            async_nor_flash::ReadNorFlash::read(&mut flash, 0, &mut bytes).await.unwrap();
+            prop_assert!(bytes == model.memory);
+            Ok(())
+        })?;
+    }
+}